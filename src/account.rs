@@ -1,4 +1,8 @@
+use crate::amount::Amount;
+use crate::errors::ErrCause;
+use serde::Deserialize;
 use serde::Serialize;
+use serde_json::value::RawValue;
 
 #[derive(Serialize)]
 pub struct ClientAccountCsvWritableRecord {
@@ -9,35 +13,148 @@ pub struct ClientAccountCsvWritableRecord {
     pub locked: bool,
 }
 
-#[derive(Debug, PartialEq)]
+/// Accounts are kept per client and currency. `None` is the unnamed
+/// currency of files without a currency column.
+pub type AccountKey = (u16, Option<String>);
+
+#[derive(Serialize)]
+pub struct CurrencyAccountCsvWritableRecord {
+    pub client: u16,
+    pub currency: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: bool,
+}
+
+/// JSON output shape. Amounts are JSON numbers written with exactly the
+/// decimal places of the account's currency.
+#[derive(Serialize)]
+pub struct ClientAccountJsonWritableRecord {
+    pub client: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    pub available: Box<RawValue>,
+    pub held: Box<RawValue>,
+    pub total: Box<RawValue>,
+    pub locked: bool,
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct ClientAccount {
     pub client: u16,
-    pub available: f64,
-    pub held: f64,
-    pub total: f64,
+    #[serde(default)]
+    pub currency: Option<String>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
     pub locked: bool,
 }
 
 impl ClientAccount {
-    pub fn deposit(&mut self, amount: f64) {
-        self.available += amount;
-        self.total += amount;
+    pub fn deposit(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let available = checked_add(self.available, amount)?;
+        let total = checked_add(self.total, amount)?;
+        self.available = available;
+        self.total = total;
+        Ok(())
+    }
+
+    pub fn withdraw(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let available = checked_sub(self.available, amount)?;
+        let total = checked_sub(self.total, amount)?;
+        self.available = available;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Moves `amount` from available to held funds.
+    pub fn hold(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let available = checked_sub(self.available, amount)?;
+        let held = checked_add(self.held, amount)?;
+        self.available = available;
+        self.held = held;
+        Ok(())
+    }
+
+    /// Moves `amount` from held back to available funds.
+    pub fn release(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let held = checked_sub(self.held, amount)?;
+        let available = checked_add(self.available, amount)?;
+        self.held = held;
+        self.available = available;
+        Ok(())
     }
 
-    pub fn withdraw(&mut self, amount: f64) {
-        self.available -= amount;
-        self.total -= amount;
+    /// Adds `amount` to held and total funds, as when a withdrawal is
+    /// disputed and the debited funds are claimed back.
+    pub fn add_held(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let held = checked_add(self.held, amount)?;
+        let total = checked_add(self.total, amount)?;
+        self.held = held;
+        self.total = total;
+        Ok(())
+    }
+
+    /// Removes `amount` from held and total funds.
+    pub fn drop_held(&mut self, amount: Amount) -> Result<(), ErrCause> {
+        let held = checked_sub(self.held, amount)?;
+        let total = checked_sub(self.total, amount)?;
+        self.held = held;
+        self.total = total;
+        Ok(())
     }
 
     pub fn writable_record(&self) -> ClientAccountCsvWritableRecord {
         ClientAccountCsvWritableRecord {
             client: self.client,
-            available: format!("{:.4}", self.available),
-            held: format!("{:.4}", self.held),
-            total: format!("{:.4}", self.total),
+            available: self.available.to_string(),
+            held: self.held.to_string(),
+            total: self.total.to_string(),
+            locked: self.locked,
+        }
+    }
+
+    /// Output row for runs with a currency column, with amounts formatted at
+    /// the precision of the account's currency.
+    pub fn writable_currency_record(&self, decimals: u32) -> CurrencyAccountCsvWritableRecord {
+        CurrencyAccountCsvWritableRecord {
+            client: self.client,
+            currency: self.currency.clone().unwrap_or_default(),
+            available: self.available.to_string_with_decimals(decimals),
+            held: self.held.to_string_with_decimals(decimals),
+            total: self.total.to_string_with_decimals(decimals),
+            locked: self.locked,
+        }
+    }
+
+    pub fn writable_json_record(&self, decimals: u32) -> ClientAccountJsonWritableRecord {
+        ClientAccountJsonWritableRecord {
+            client: self.client,
+            currency: self.currency.clone(),
+            available: json_number(self.available, decimals),
+            held: json_number(self.held, decimals),
+            total: json_number(self.total, decimals),
             locked: self.locked,
         }
     }
+
+    pub fn key(&self) -> AccountKey {
+        (self.client, self.currency.clone())
+    }
+}
+
+fn json_number(amount: Amount, decimals: u32) -> Box<RawValue> {
+    RawValue::from_string(amount.to_string_with_decimals(decimals))
+        .expect("a formatted amount is a valid JSON number")
+}
+
+fn checked_add(lhs: Amount, rhs: Amount) -> Result<Amount, ErrCause> {
+    lhs.checked_add(rhs).ok_or(ErrCause::AmountOverflow)
+}
+
+fn checked_sub(lhs: Amount, rhs: Amount) -> Result<Amount, ErrCause> {
+    lhs.checked_sub(rhs).ok_or(ErrCause::AmountOverflow)
 }
 
 #[cfg(test)]
@@ -48,21 +165,23 @@ mod tests {
     fn deposit_increases_available_and_total_amounts() {
         let mut account = ClientAccount {
             client: 1,
-            available: 10.0,
-            held: 0.0,
-            total: 10.0,
+            currency: None,
+            available: "10.0".parse().unwrap(),
+            held: "0.0".parse().unwrap(),
+            total: "10.0".parse().unwrap(),
             locked: false,
         };
 
-        account.deposit(10.0);
+        account.deposit("10.0".parse().unwrap()).unwrap();
 
         assert_eq!(
             account,
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
                 locked: false
             }
         )
@@ -72,21 +191,23 @@ mod tests {
     fn withdraw_decreases_available_and_total_amounts() {
         let mut account = ClientAccount {
             client: 1,
-            available: 10.0,
-            held: 0.0,
-            total: 10.0,
+            currency: None,
+            available: "10.0".parse().unwrap(),
+            held: "0.0".parse().unwrap(),
+            total: "10.0".parse().unwrap(),
             locked: false,
         };
 
-        account.withdraw(10.0);
+        account.withdraw("10.0".parse().unwrap()).unwrap();
 
         assert_eq!(
             account,
             ClientAccount {
                 client: 1,
-                available: 0.0,
-                held: 0.0,
-                total: 0.0,
+                currency: None,
+                available: "0.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "0.0".parse().unwrap(),
                 locked: false
             }
         )
@@ -0,0 +1,222 @@
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::error::Error;
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Number of decimal places every amount is carried with.
+pub const DECIMALS: u32 = 4;
+
+const SCALE: i64 = 10_i64.pow(DECIMALS);
+
+/// Fixed-point monetary amount with four decimal places, stored as an integer
+/// count of 1/10000 units so that balances never accumulate rounding error.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Amount(i64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub const fn from_raw(units: i64) -> Self {
+        Amount(units)
+    }
+
+    pub const fn raw(&self) -> i64 {
+        self.0
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.0 > 0
+    }
+
+    /// Whether the amount needs no more than `decimals` decimal places.
+    pub fn has_at_most_decimals(&self, decimals: u32) -> bool {
+        decimals >= DECIMALS || self.0 % 10_i64.pow(DECIMALS - decimals) == 0
+    }
+
+    /// Formats the amount with `decimals` decimal places, dropping trailing
+    /// digits beyond that.
+    pub fn to_string_with_decimals(&self, decimals: u32) -> String {
+        let formatted = self.to_string();
+        let dropped = DECIMALS.saturating_sub(decimals) as usize;
+        let formatted = &formatted[..formatted.len() - dropped];
+        formatted.strip_suffix('.').unwrap_or(formatted).to_string()
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ParseAmountError(String);
+
+impl Error for ParseAmountError {}
+
+impl Display for ParseAmountError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid amount: {}", self.0)
+    }
+}
+
+impl FromStr for Amount {
+    type Err = ParseAmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseAmountError(s.to_string());
+
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, fraction),
+            None => (digits, ""),
+        };
+
+        if (whole.is_empty() && fraction.is_empty())
+            || fraction.len() > DECIMALS as usize
+            || !whole.bytes().all(|b| b.is_ascii_digit())
+            || !fraction.bytes().all(|b| b.is_ascii_digit())
+        {
+            return Err(invalid());
+        }
+
+        let whole: i64 = if whole.is_empty() {
+            0
+        } else {
+            whole.parse().map_err(|_| invalid())?
+        };
+        let fraction: i64 = if fraction.is_empty() {
+            0
+        } else {
+            fraction.parse::<i64>().map_err(|_| invalid())?
+                * 10_i64.pow(DECIMALS - fraction.len() as u32)
+        };
+
+        let units = whole
+            .checked_mul(SCALE)
+            .and_then(|units| units.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(Amount(if negative { -units } else { units }))
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let units = self.0.unsigned_abs();
+        let scale = SCALE as u64;
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            units / scale,
+            units % scale,
+            width = DECIMALS as usize
+        )
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+struct AmountVisitor;
+
+impl Visitor<'_> for AmountVisitor {
+    type Value = Amount;
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "a decimal amount with at most {} decimal places",
+            DECIMALS
+        )
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Amount, E> {
+        v.parse().map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(AmountVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Amount;
+
+    #[test]
+    fn parses_whole_and_fractional_amounts() {
+        assert_eq!("1".parse::<Amount>().unwrap(), Amount::from_raw(10_000));
+        assert_eq!("1.5".parse::<Amount>().unwrap(), Amount::from_raw(15_000));
+        assert_eq!("0.0001".parse::<Amount>().unwrap(), Amount::from_raw(1));
+        assert_eq!(".25".parse::<Amount>().unwrap(), Amount::from_raw(2_500));
+        assert_eq!(
+            "-10.0".parse::<Amount>().unwrap(),
+            Amount::from_raw(-100_000)
+        );
+    }
+
+    #[test]
+    fn rejects_more_than_four_decimal_places() {
+        assert!("1.00001".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        assert!("".parse::<Amount>().is_err());
+        assert!(".".parse::<Amount>().is_err());
+        assert!("1.2.3".parse::<Amount>().is_err());
+        assert!("1e5".parse::<Amount>().is_err());
+        assert!("99999999999999999999".parse::<Amount>().is_err());
+    }
+
+    #[test]
+    fn displays_with_four_decimal_places() {
+        assert_eq!(Amount::from_raw(15_000).to_string(), "1.5000");
+        assert_eq!(Amount::from_raw(-1).to_string(), "-0.0001");
+        assert_eq!(Amount::ZERO.to_string(), "0.0000");
+    }
+
+    #[test]
+    fn displays_with_fewer_decimal_places() {
+        let amount: Amount = "1234.5".parse().unwrap();
+        assert_eq!(amount.to_string_with_decimals(2), "1234.50");
+        assert_eq!(amount.to_string_with_decimals(0), "1234");
+        assert_eq!(amount.to_string_with_decimals(4), "1234.5000");
+    }
+
+    #[test]
+    fn repeated_additions_are_exact() {
+        let tenth: Amount = "0.1".parse().unwrap();
+        let mut sum = Amount::ZERO;
+        for _ in 0..10 {
+            sum = sum.checked_add(tenth).unwrap();
+        }
+        assert_eq!(sum, "1".parse().unwrap());
+    }
+
+    #[test]
+    fn checked_add_reports_overflow() {
+        assert_eq!(
+            Amount::from_raw(i64::MAX).checked_add(Amount::from_raw(1)),
+            None
+        );
+        assert_eq!(
+            Amount::from_raw(i64::MIN).checked_sub(Amount::from_raw(1)),
+            None
+        );
+    }
+}
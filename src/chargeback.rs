@@ -1,21 +1,22 @@
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use crate::tx::Tx;
 
 #[derive(Debug)]
 pub struct Chargeback {
     pub client: u16,
     pub tx: u32,
+    pub timestamp: Option<u64>,
 }
 
 impl Tx for Chargeback {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError> {
-        visitor.process_chargeback(state, self)
+        visitor.process_chargeback(storage, self)
     }
 }
 
@@ -30,14 +31,20 @@ mod tests {
     use crate::state::AppState;
     use crate::state::Flag;
     use crate::state::FlaggedDeposit;
+    use crate::state::FlaggedWithdrawal;
     use crate::tx::Tx;
+    use crate::withdrawal::Withdrawal;
 
     #[test]
     fn chargeback_fails_on_non_existent_deposit() {
-        let chargeback = Chargeback { client: 1, tx: 2 };
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         let err = chargeback
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
 
         assert_eq!(
@@ -53,6 +60,7 @@ mod tests {
         let chargeback = Chargeback {
             client: client_id,
             tx: tx_id,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.deposits.insert(
@@ -61,24 +69,28 @@ mod tests {
                 deposit: Deposit {
                     client: client_id,
                     tx: tx_id,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
         state.accounts.insert(
-            client_id,
+            (client_id, None),
             ClientAccount {
                 client: client_id,
-                available: 10.0,
-                held: 0.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
 
         let err = chargeback
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
         assert_eq!(
             err,
@@ -88,7 +100,11 @@ mod tests {
 
     #[test]
     fn dispute_fails_if_client_in_does_not_match() {
-        let chargeback = Chargeback { client: 1, tx: 1 };
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -96,14 +112,17 @@ mod tests {
                 deposit: Deposit {
                     client: 2,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
 
         let err = chargeback
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
         assert_eq!(
             err,
@@ -114,7 +133,11 @@ mod tests {
     #[test]
     #[should_panic]
     fn chargeback_panics_if_account_is_absent_and_diposit_is_present() {
-        let chargeback = Chargeback { client: 1, tx: 1 };
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -122,20 +145,27 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
         chargeback
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
     }
 
     #[test]
     fn chargeback_locks_the_account() {
-        let chargeback = Chargeback { client: 1, tx: 1 };
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -143,31 +173,41 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        chargeback.process(&mut state, &TxProcessorImpl).unwrap();
+        chargeback
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
-        assert_eq!(state.accounts.get(&1).unwrap().locked, true);
+        assert!(state.accounts.get(&(1, None)).unwrap().locked);
     }
 
     #[test]
     fn chargeback_marks_the_deposit_chargebacked() {
-        let chargeback = Chargeback { client: 1, tx: 1 };
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -175,25 +215,83 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        chargeback.process(&mut state, &TxProcessorImpl).unwrap();
+        chargeback
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(state.deposits.get(&1).unwrap().flag, Flag::Chargebacked);
     }
+
+    #[test]
+    fn chargeback_of_withdrawal_returns_the_funds_and_locks_the_account() {
+        let chargeback = Chargeback {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.withdrawals.insert(
+            1,
+            FlaggedWithdrawal {
+                withdrawal: Withdrawal {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Disputed,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "20.0".parse().unwrap(),
+                total: "30.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        chargeback
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap(),
+            &ClientAccount {
+                client: 1,
+                currency: None,
+                available: "30.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "30.0".parse().unwrap(),
+                locked: true,
+            }
+        );
+        assert_eq!(state.withdrawals.get(&1).unwrap().flag, Flag::Chargebacked);
+    }
 }
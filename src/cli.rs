@@ -0,0 +1,378 @@
+use std::error::Error;
+use std::path::PathBuf;
+use tx_engine::currency::CurrencyConfig;
+use tx_engine::general_ledger::ChartOfAccounts;
+use tx_engine::input::InputFormat;
+use tx_engine::output::AccountOrder;
+use tx_engine::output::OutputFormat;
+use tx_engine::state::OutOfOrderPolicy;
+
+pub const USAGE: &str = "Usage: tx_engine [options] <transactions file>... \
+or cargo run -- [options] <transactions file>...
+       (files are processed in order, - reads from stdin)
+       tx_engine serve|http <address> [--shards <n>] [--currency <cur:n>] \
+[--allow-redispute] [--dispute-window <n>] [--dispute-window-secs <n>]
+                                [--out-of-order <policy>] [--snapshot-in <file>]
+
+Options:
+    --input-format <fmt>   csv or jsonl (default: jsonl for .jsonl/.ndjson files, else csv)
+    --output-format <fmt>  csv (default), json or jsonl
+    --sort <order>         order accounts by client (default), total (highest first) or
+                           locked (locked first)
+    --rejections <file>    write rejected transactions to <file> (.jsonl for JSON Lines)
+    --lenient              skip malformed rows instead of aborting
+    --quarantine <file>    write malformed rows to <file> (requires --lenient)
+    --currency <cur:n>     amounts in currency <cur> have <n> decimal places (at most 4),
+                           may be repeated, e.g. --currency JPY:0 --currency USD:2
+    --allow-redispute      allow resolved transactions to be disputed again
+    --dispute-window <n>   a deposit or withdrawal can only be disputed until <n> further
+                           deposits, withdrawals and transfers have been accepted
+    --dispute-window-secs <n>
+                           a deposit or withdrawal can only be disputed until <n> seconds
+                           after its timestamp; with --dispute-window both apply, and
+                           only --dispute-window without timestamps
+    --out-of-order <p>     reject (default) or accept transactions whose timestamp is
+                           earlier than the latest one accepted for their client
+    --snapshot-in <file>   start from the engine state saved in <file>
+    --snapshot-out <file>  save the engine state to <file> at the end of the run
+    --ledger <file>        write every applied transaction with the resulting balances
+                           to <file>
+    --gl-postings <file>   write the double-entry general ledger postings to <file>
+    --trial-balance <file> write the general ledger trial balance to <file> at the end
+    --gl-account <a>=<n>   name general ledger account <a> (client_available, client_held,
+                           settlement_cash or chargeback_losses) <n>, may be repeated
+    --journal <file>       write accepted transactions to a write-ahead journal
+    --checkpoint <file>    save the engine state to <file> every --checkpoint-every rows
+    --checkpoint-every <n> rows between checkpoints (default 100000)
+    --storage <file>       keep the engine state in the SQLite database <file> instead of
+                           memory; it carries over between runs (needs the sqlite feature,
+                           cannot be combined with --shards, --snapshot-in, --snapshot-out,
+                           --checkpoint or --recover)
+    --spill <dir>          keep at most --spill-after undisputed transactions in memory and
+                           move older ones to an index in <dir> (same restrictions as
+                           --storage)
+    --spill-after <n>      undisputed transactions kept in memory (default 1000000)
+    --shards <n>           apply transactions on <n> worker threads, partitioned by client;
+                           a transfer fails the run (serve and http reject it)
+                           (cannot be combined with --journal, --checkpoint, --recover,
+                           --dispute-window, --ledger or --gl-postings)
+    --recover              rebuild the state of a crashed run from --checkpoint (or
+                           --snapshot-in) and --journal, then resume after the last
+                           journaled row (cannot be combined with --ledger,
+                           --gl-postings or --trial-balance)";
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ParseMode {
+    Strict,
+    Lenient,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Process transaction files in order and print the accounts.
+    Process(Vec<PathBuf>),
+    /// Accept transaction streams over TCP on the given address.
+    Serve(String),
+    /// Serve the HTTP JSON API on the given address.
+    Http(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub command: Command,
+    pub input_format: Option<InputFormat>,
+    pub output_format: OutputFormat,
+    pub sort: AccountOrder,
+    pub rejections: Option<PathBuf>,
+    pub mode: ParseMode,
+    pub quarantine: Option<PathBuf>,
+    pub allow_redispute: bool,
+    pub dispute_window: Option<u64>,
+    pub dispute_window_secs: Option<u64>,
+    pub out_of_order: OutOfOrderPolicy,
+    pub currencies: CurrencyConfig,
+    pub snapshot_in: Option<PathBuf>,
+    pub snapshot_out: Option<PathBuf>,
+    pub ledger: Option<PathBuf>,
+    pub gl_postings: Option<PathBuf>,
+    pub trial_balance: Option<PathBuf>,
+    pub chart: ChartOfAccounts,
+    pub journal: Option<PathBuf>,
+    pub checkpoint: Option<PathBuf>,
+    pub checkpoint_every: u64,
+    pub recover: bool,
+    pub shards: usize,
+    pub storage: Option<PathBuf>,
+    pub spill: Option<PathBuf>,
+    pub spill_after: usize,
+}
+
+impl Options {
+    pub fn parse<I: Iterator<Item = String>>(args: I) -> Result<Self, Box<dyn Error>> {
+        let mut args = args.peekable();
+        let serve = match args.peek().map(String::as_str) {
+            Some("serve") | Some("http") => {
+                let command = args.next().unwrap_or_default();
+                let address = value(&command, args.next())?;
+                Some(match command.as_str() {
+                    "serve" => Command::Serve(address),
+                    _ => Command::Http(address),
+                })
+            }
+            _ => None,
+        };
+        let mut tx_files = Vec::new();
+        let mut input_format = None;
+        let mut output_format = OutputFormat::Csv;
+        let mut sort = AccountOrder::Client;
+        let mut rejections = None;
+        let mut mode = ParseMode::Strict;
+        let mut quarantine = None;
+        let mut allow_redispute = false;
+        let mut dispute_window = None;
+        let mut dispute_window_secs = None;
+        let mut out_of_order = OutOfOrderPolicy::default();
+        let mut currencies = CurrencyConfig::new();
+        let mut snapshot_in = None;
+        let mut snapshot_out = None;
+        let mut ledger = None;
+        let mut gl_postings = None;
+        let mut trial_balance = None;
+        let mut chart = ChartOfAccounts::new();
+        let mut journal = None;
+        let mut checkpoint = None;
+        let mut checkpoint_every = 100_000;
+        let mut recover = false;
+        let mut shards = 1;
+        let mut storage = None;
+        let mut spill = None;
+        let mut spill_after = 1_000_000;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--input-format" => {
+                    input_format = Some(
+                        InputFormat::parse(&value(&arg, args.next())?)
+                            .map_err(|err| format!("{}\n{}", err, USAGE))?,
+                    )
+                }
+                "--output-format" => {
+                    output_format = OutputFormat::parse(&value(&arg, args.next())?)
+                        .map_err(|err| format!("{}\n{}", err, USAGE))?
+                }
+                "--sort" => {
+                    sort = AccountOrder::parse(&value(&arg, args.next())?)
+                        .map_err(|err| format!("{}\n{}", err, USAGE))?
+                }
+                "--rejections" => rejections = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--lenient" => mode = ParseMode::Lenient,
+                "--allow-redispute" => allow_redispute = true,
+                "--dispute-window" => {
+                    dispute_window = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|window| *window > 0)
+                            .ok_or_else(|| {
+                                format!("--dispute-window expects a positive number\n{}", USAGE)
+                            })?,
+                    )
+                }
+                "--dispute-window-secs" => {
+                    dispute_window_secs = Some(
+                        value(&arg, args.next())?
+                            .parse()
+                            .ok()
+                            .filter(|window| *window > 0)
+                            .ok_or_else(|| {
+                                format!(
+                                    "--dispute-window-secs expects a positive number\n{}",
+                                    USAGE
+                                )
+                            })?,
+                    )
+                }
+                "--out-of-order" => {
+                    out_of_order = OutOfOrderPolicy::parse(&value(&arg, args.next())?)
+                        .map_err(|err| format!("{}\n{}", err, USAGE))?
+                }
+                "--currency" => currencies.parse_setting(&value(&arg, args.next())?)?,
+                "--snapshot-in" => snapshot_in = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--snapshot-out" => snapshot_out = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--ledger" => ledger = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--gl-postings" => gl_postings = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--trial-balance" => trial_balance = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--gl-account" => chart.parse_setting(&value(&arg, args.next())?)?,
+                "--journal" => journal = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--checkpoint" => checkpoint = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--checkpoint-every" => {
+                    checkpoint_every = value(&arg, args.next())?
+                        .parse()
+                        .ok()
+                        .filter(|every| *every > 0)
+                        .ok_or_else(|| {
+                            format!("--checkpoint-every expects a positive number\n{}", USAGE)
+                        })?
+                }
+                "--recover" => recover = true,
+                "--shards" => {
+                    shards = value(&arg, args.next())?
+                        .parse()
+                        .ok()
+                        .filter(|shards| *shards > 0)
+                        .ok_or_else(|| format!("--shards expects a positive number\n{}", USAGE))?
+                }
+                "--storage" => storage = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--spill" => spill = Some(PathBuf::from(value(&arg, args.next())?)),
+                "--spill-after" => {
+                    spill_after = value(&arg, args.next())?
+                        .parse()
+                        .ok()
+                        .filter(|after| *after > 0)
+                        .ok_or_else(|| {
+                            format!("--spill-after expects a positive number\n{}", USAGE)
+                        })?
+                }
+                "--quarantine" => quarantine = Some(PathBuf::from(value(&arg, args.next())?)),
+                flag if flag.starts_with("--") => {
+                    return Err(Box::<dyn Error>::from(format!(
+                        "Unknown option: {}\n{}",
+                        flag, USAGE
+                    )))
+                }
+                _ if serve.is_none() => tx_files.push(PathBuf::from(arg)),
+                _ => return Err(Box::<dyn Error>::from(USAGE)),
+            }
+        }
+
+        if quarantine.is_some() && mode == ParseMode::Strict {
+            return Err(Box::<dyn Error>::from(format!(
+                "--quarantine requires --lenient\n{}",
+                USAGE
+            )));
+        }
+
+        if recover && journal.is_none() {
+            return Err(Box::<dyn Error>::from(format!(
+                "--recover requires --journal\n{}",
+                USAGE
+            )));
+        }
+
+        // The replay does not go through the ledger and the general ledger, so
+        // their files would only cover the rows after the crash.
+        if recover && (ledger.is_some() || gl_postings.is_some() || trial_balance.is_some()) {
+            return Err(Box::<dyn Error>::from(format!(
+                "--recover cannot be combined with --ledger, --gl-postings or --trial-balance\n{}",
+                USAGE
+            )));
+        }
+
+        // The ledger and the postings list entries in the order they are
+        // applied, which across shards depends on thread timing.
+        if shards > 1
+            && (journal.is_some()
+                || checkpoint.is_some()
+                || recover
+                || dispute_window.is_some()
+                || ledger.is_some()
+                || gl_postings.is_some())
+        {
+            return Err(Box::<dyn Error>::from(format!(
+                "--shards cannot be combined with --journal, --checkpoint, --recover, \
+                 --dispute-window, --ledger or --gl-postings\n{}",
+                USAGE
+            )));
+        }
+
+        if storage.is_some() && !cfg!(feature = "sqlite") {
+            return Err(Box::<dyn Error>::from(format!(
+                "--storage requires a build with the sqlite feature\n{}",
+                USAGE
+            )));
+        }
+
+        if storage.is_some() || spill.is_some() {
+            if storage.is_some() && spill.is_some() {
+                return Err(Box::<dyn Error>::from(format!(
+                    "--storage cannot be combined with --spill\n{}",
+                    USAGE
+                )));
+            }
+            if shards > 1
+                || snapshot_in.is_some()
+                || snapshot_out.is_some()
+                || checkpoint.is_some()
+                || recover
+            {
+                return Err(Box::<dyn Error>::from(format!(
+                    "--storage and --spill cannot be combined with --shards, --snapshot-in, \
+                     --snapshot-out, --checkpoint or --recover\n{}",
+                    USAGE
+                )));
+            }
+        }
+
+        let command = match (serve, tx_files.is_empty()) {
+            (Some(command), true) => {
+                if rejections.is_some()
+                    || input_format.is_some()
+                    || output_format != OutputFormat::Csv
+                    || sort != AccountOrder::Client
+                    || mode == ParseMode::Lenient
+                    || snapshot_out.is_some()
+                    || ledger.is_some()
+                    || gl_postings.is_some()
+                    || trial_balance.is_some()
+                    || journal.is_some()
+                    || checkpoint.is_some()
+                    || storage.is_some()
+                    || spill.is_some()
+                {
+                    return Err(Box::<dyn Error>::from(format!(
+                        "serve and http only accept --shards, --currency, --allow-redispute, \
+                         --dispute-window, --dispute-window-secs, --out-of-order and \
+                         --snapshot-in\n{}",
+                        USAGE
+                    )));
+                }
+                command
+            }
+            (None, false) => Command::Process(tx_files),
+            _ => return Err(Box::<dyn Error>::from(USAGE)),
+        };
+
+        Ok(Self {
+            command,
+            input_format,
+            output_format,
+            sort,
+            rejections,
+            mode,
+            quarantine,
+            allow_redispute,
+            dispute_window,
+            dispute_window_secs,
+            out_of_order,
+            currencies,
+            snapshot_in,
+            snapshot_out,
+            ledger,
+            gl_postings,
+            trial_balance,
+            chart,
+            journal,
+            checkpoint,
+            checkpoint_every,
+            recover,
+            shards,
+            storage,
+            spill,
+            spill_after,
+        })
+    }
+}
+
+fn value(flag: &str, value: Option<String>) -> Result<String, Box<dyn Error>> {
+    value.ok_or_else(|| Box::<dyn Error>::from(format!("Missing value for {}\n{}", flag, USAGE)))
+}
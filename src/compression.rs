@@ -0,0 +1,121 @@
+use flate2::read::MultiGzDecoder;
+use std::io;
+use std::io::BufReader;
+use std::io::Cursor;
+use std::io::Read;
+use std::path::Path;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// `.gz` and `.zst` files are compressed; for anything else the
+    /// compression is detected from the content.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Some(Compression::Gzip),
+            Some("zst") => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn detect(head: &[u8]) -> Self {
+        if head.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if head.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Path of the content once decompressed, e.g. `day.csv` for `day.csv.gz`.
+pub fn uncompressed_path(path: &Path) -> &Path {
+    match Compression::from_path(path) {
+        Some(_) => path.file_stem().map(Path::new).unwrap_or(path),
+        None => path,
+    }
+}
+
+/// Wraps `reader` in a streaming decoder for `compression`, or for the
+/// compression detected from the first bytes if it is `None`.
+pub fn decompress<'a, R: Read + 'a>(
+    mut reader: R,
+    compression: Option<Compression>,
+) -> io::Result<Box<dyn Read + 'a>> {
+    // A single read may return fewer bytes than a magic number, e.g. from a
+    // pipe, so read the head until it is complete or the input ends.
+    let mut head = Vec::with_capacity(ZSTD_MAGIC.len());
+    if compression.is_none() {
+        reader
+            .by_ref()
+            .take(ZSTD_MAGIC.len() as u64)
+            .read_to_end(&mut head)?;
+    }
+    let compression = compression.unwrap_or_else(|| Compression::detect(&head));
+    let reader = BufReader::new(Cursor::new(head).chain(reader));
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(reader)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decompress;
+    use super::uncompressed_path;
+    use super::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Read;
+    use std::io::Write;
+    use std::path::Path;
+
+    const CSV: &str = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+
+    fn read_all(input: &[u8], compression: Option<Compression>) -> String {
+        let mut out = String::new();
+        decompress(input, compression)
+            .unwrap()
+            .read_to_string(&mut out)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn gzip_and_zstd_are_detected_from_content() {
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(CSV.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = zstd::encode_all(CSV.as_bytes(), 0).unwrap();
+
+        assert_eq!(read_all(&gzip, None), CSV);
+        assert_eq!(read_all(&zstd, None), CSV);
+        assert_eq!(read_all(CSV.as_bytes(), None), CSV);
+        assert_eq!(read_all(b"", None), "");
+    }
+
+    #[test]
+    fn compression_extension_is_stripped_for_the_inner_format() {
+        assert_eq!(
+            Compression::from_path(Path::new("day.jsonl.zst")),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(
+            uncompressed_path(Path::new("archive/day.jsonl.gz")),
+            Path::new("day.jsonl")
+        );
+        assert_eq!(
+            uncompressed_path(Path::new("day.csv")),
+            Path::new("day.csv")
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::amount::Amount;
+use crate::amount::DECIMALS;
+use std::collections::HashMap;
+use std::error::Error;
+
+/// Decimal precision of each currency. Amounts are always carried with four
+/// decimal places, so a currency can use at most four; currencies that are
+/// not configured, and transactions without a currency, use all four.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CurrencyConfig {
+    decimals: HashMap<String, u32>,
+}
+
+impl CurrencyConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_decimals(&mut self, currency: &str, decimals: u32) -> Result<(), Box<dyn Error>> {
+        if decimals > DECIMALS {
+            return Err(Box::<dyn Error>::from(format!(
+                "{} cannot have more than {} decimal places",
+                currency, DECIMALS
+            )));
+        }
+        self.decimals.insert(currency.to_string(), decimals);
+        Ok(())
+    }
+
+    /// Parses a `<currency>:<decimals>` setting such as `JPY:0`.
+    pub fn parse_setting(&mut self, setting: &str) -> Result<(), Box<dyn Error>> {
+        let invalid = || {
+            Box::<dyn Error>::from(format!(
+                "Invalid currency setting {}, expected <currency>:<decimals>",
+                setting
+            ))
+        };
+        let (currency, decimals) = setting.split_once(':').ok_or_else(invalid)?;
+        let decimals = decimals.parse().map_err(|_| invalid())?;
+        if currency.is_empty() {
+            return Err(invalid());
+        }
+        self.set_decimals(currency, decimals)
+    }
+
+    pub fn decimals(&self, currency: Option<&str>) -> u32 {
+        currency
+            .and_then(|currency| self.decimals.get(currency))
+            .copied()
+            .unwrap_or(DECIMALS)
+    }
+
+    /// Whether `amount` can be expressed in `currency` without rounding.
+    pub fn accepts(&self, currency: Option<&str>, amount: Amount) -> bool {
+        amount.has_at_most_decimals(self.decimals(currency))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CurrencyConfig;
+
+    #[test]
+    fn unconfigured_currencies_use_four_decimals() {
+        let currencies = CurrencyConfig::new();
+
+        assert_eq!(currencies.decimals(None), 4);
+        assert_eq!(currencies.decimals(Some("EUR")), 4);
+        assert!(currencies.accepts(Some("EUR"), "0.0001".parse().unwrap()));
+    }
+
+    #[test]
+    fn configured_precision_limits_accepted_amounts() {
+        let mut currencies = CurrencyConfig::new();
+        currencies.parse_setting("JPY:0").unwrap();
+        currencies.parse_setting("USD:2").unwrap();
+
+        assert!(currencies.accepts(Some("JPY"), "100".parse().unwrap()));
+        assert!(!currencies.accepts(Some("JPY"), "100.5".parse().unwrap()));
+        assert!(currencies.accepts(Some("USD"), "1.25".parse().unwrap()));
+        assert!(!currencies.accepts(Some("USD"), "1.255".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_settings_are_refused() {
+        let mut currencies = CurrencyConfig::new();
+
+        assert!(currencies.parse_setting("USD").is_err());
+        assert!(currencies.parse_setting("USD:x").is_err());
+        assert!(currencies.parse_setting(":2").is_err());
+        assert!(currencies.parse_setting("BTC:8").is_err());
+    }
+}
@@ -1,22 +1,29 @@
+use crate::amount::Amount;
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use crate::tx::Tx;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Deposit {
     pub client: u16,
     pub tx: u32,
-    pub amount: f64,
+    pub amount: Amount,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 impl Tx for Deposit {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError> {
-        visitor.process_deposit(state, self)
+        visitor.process_deposit(storage, self)
     }
 }
 
@@ -24,6 +31,8 @@ impl Tx for Deposit {
 mod tests {
     use super::Deposit;
     use crate::account::ClientAccount;
+    use crate::amount::Amount;
+    use crate::currency::CurrencyConfig;
     use crate::errors::ErrCause;
     use crate::errors::TxProcessingError;
     use crate::processor::TxProcessorImpl;
@@ -37,18 +46,23 @@ mod tests {
         let deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: 200.1234,
+            amount: "200.1234".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
-        deposit.process(&mut state, &TxProcessorImpl).unwrap();
+        deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
-            state.accounts.get(&deposit.client).unwrap(),
+            state.accounts.get(&(deposit.client, None)).unwrap(),
             &ClientAccount {
                 client: 1,
-                available: 200.1234,
-                held: 0.0,
-                total: 200.1234,
+                currency: None,
+                available: "200.1234".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "200.1234".parse().unwrap(),
                 locked: false,
             }
         )
@@ -59,28 +73,34 @@ mod tests {
         let deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: 200.1234,
+            amount: "200.1234".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 10.0,
-                held: 0.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
-        deposit.process(&mut state, &TxProcessorImpl).unwrap();
+        deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
-            state.accounts.get(&deposit.client).unwrap(),
+            state.accounts.get(&(deposit.client, None)).unwrap(),
             &ClientAccount {
                 client: 1,
-                available: 210.1234,
-                held: 0.0,
-                total: 210.1234,
+                currency: None,
+                available: "210.1234".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "210.1234".parse().unwrap(),
                 locked: false,
             }
         )
@@ -91,28 +111,34 @@ mod tests {
         let deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: 200.1234,
+            amount: "200.1234".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 10.0,
-                held: 100.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
-        deposit.process(&mut state, &TxProcessorImpl).unwrap();
+        deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
-            state.accounts.get(&deposit.client).unwrap(),
+            state.accounts.get(&(deposit.client, None)).unwrap(),
             &ClientAccount {
                 client: 1,
-                available: 210.1234,
-                held: 100.0,
-                total: 210.1234,
+                currency: None,
+                available: "210.1234".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "210.1234".parse().unwrap(),
                 locked: false,
             }
         )
@@ -123,10 +149,14 @@ mod tests {
         let deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: 200.1234,
+            amount: "200.1234".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
-        deposit.process(&mut state, &TxProcessorImpl).unwrap();
+        deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
             state.deposits.get(&deposit.tx).unwrap(),
@@ -134,9 +164,12 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 2,
-                    amount: 200.1234,
+                    amount: "200.1234".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
-                flag: Flag::NotDisputed
+                flag: Flag::NotDisputed,
+                seq: 0,
             }
         )
     }
@@ -146,11 +179,13 @@ mod tests {
         let negative_amount_deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: -10.0,
+            amount: "-10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         let negative_deposit_processing_error = negative_amount_deposit
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
         assert_eq!(
             negative_deposit_processing_error,
@@ -163,11 +198,13 @@ mod tests {
         let zero_amount_deposit = Deposit {
             client: 1,
             tx: 2,
-            amount: 0.0,
+            amount: "0.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         let zero_deposit_processing_error = zero_amount_deposit
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
         assert_eq!(
             zero_deposit_processing_error,
@@ -180,25 +217,172 @@ mod tests {
         let client = 1;
         let mut state = AppState::new();
         state.accounts.insert(
-            client,
+            (client, None),
             ClientAccount {
-                client: client,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
+                client,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: true,
+                currency: None,
             },
         );
         let deposit = Deposit {
-            client: client,
+            client,
             tx: 1,
-            amount: 10.0,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
-        let deposit_error = deposit.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let deposit_error = deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
 
         assert_eq!(
             deposit_error,
             TxProcessingError::Deposit(ErrCause::ClientAccountLocked)
         );
     }
+
+    #[test]
+    fn deposit_fails_if_balance_would_overflow() {
+        let mut state = AppState::new();
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: Amount::from_raw(i64::MAX),
+                held: Amount::ZERO,
+                total: Amount::from_raw(i64::MAX),
+                locked: false,
+            },
+        );
+        let deposit = Deposit {
+            client: 1,
+            tx: 1,
+            amount: "0.0001".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        };
+        let deposit_error = deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(
+            deposit_error,
+            TxProcessingError::Deposit(ErrCause::AmountOverflow)
+        );
+        assert_eq!(state.deposits.get(&1), None);
+    }
+
+    #[test]
+    fn deposit_with_duplicate_tx_id_is_rejected() {
+        let mut state = AppState::new();
+        Deposit {
+            client: 1,
+            tx: 1,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+
+        let duplicate_error = Deposit {
+            client: 2,
+            tx: 1,
+            amount: "5.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap_err();
+
+        assert_eq!(
+            duplicate_error,
+            TxProcessingError::Deposit(ErrCause::DuplicateTransactionId)
+        );
+        assert_eq!(
+            state.deposits.get(&1).unwrap().deposit.amount,
+            "10.0".parse().unwrap()
+        );
+        assert_eq!(state.accounts.get(&(2, None)), None);
+    }
+
+    #[test]
+    fn rejected_deposit_does_not_reserve_its_tx_id() {
+        let mut state = AppState::new();
+        Deposit {
+            client: 1,
+            tx: 1,
+            amount: "0.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap_err();
+
+        Deposit {
+            client: 1,
+            tx: 1,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+    }
+
+    #[test]
+    fn deposits_in_different_currencies_are_kept_apart() {
+        let mut state = AppState::new();
+        for (tx, currency) in [(1, None), (2, Some("EUR")), (3, Some("EUR"))] {
+            Deposit {
+                client: 1,
+                tx,
+                amount: "10.0".parse().unwrap(),
+                currency: currency.map(str::to_string),
+                timestamp: None,
+            }
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+        }
+
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().total,
+            "10.0".parse().unwrap()
+        );
+        assert_eq!(
+            state
+                .accounts
+                .get(&(1, Some("EUR".to_string())))
+                .unwrap()
+                .total,
+            "20.0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn deposit_fails_if_amount_is_finer_than_currency_precision() {
+        let mut currencies = CurrencyConfig::new();
+        currencies.set_decimals("JPY", 0).unwrap();
+        let processor = TxProcessorImpl::new().with_currencies(currencies);
+        let mut state = AppState::new();
+        let deposit_error = Deposit {
+            client: 1,
+            tx: 1,
+            amount: "100.5".parse().unwrap(),
+            currency: Some("JPY".to_string()),
+            timestamp: None,
+        }
+        .process(&mut state, &processor)
+        .unwrap_err();
+
+        assert_eq!(
+            deposit_error,
+            TxProcessingError::Deposit(ErrCause::AmountPrecisionExceeded)
+        );
+        assert!(state.accounts.is_empty());
+    }
 }
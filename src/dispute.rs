@@ -1,21 +1,22 @@
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use crate::tx::Tx;
 
 #[derive(Debug)]
 pub struct Dispute {
     pub client: u16,
     pub tx: u32,
+    pub timestamp: Option<u64>,
 }
 
 impl Tx for Dispute {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError> {
-        visitor.process_dispute(state, self)
+        visitor.process_dispute(storage, self)
     }
 }
 
@@ -27,16 +28,27 @@ mod tests {
     use crate::errors::ErrCause;
     use crate::errors::TxProcessingError;
     use crate::processor::TxProcessorImpl;
+    use crate::resolve::Resolve;
     use crate::state::AppState;
+    use crate::state::DisputePolicy;
     use crate::state::Flag;
     use crate::state::FlaggedDeposit;
+    use crate::state::FlaggedWithdrawal;
+    use crate::transfer::Transfer;
     use crate::tx::Tx;
+    use crate::withdrawal::Withdrawal;
 
     #[test]
     fn dispute_fails_on_non_existent_deposit() {
-        let dispute = Dispute { client: 1, tx: 2 };
+        let dispute = Dispute {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        };
         let mut state = AppState::new();
-        let dispute_processing_error = dispute.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let dispute_processing_error = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
 
         assert_eq!(
             dispute_processing_error,
@@ -51,6 +63,7 @@ mod tests {
         let dispute = Dispute {
             client: client_id,
             tx: tx_id,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.deposits.insert(
@@ -59,23 +72,29 @@ mod tests {
                 deposit: Deposit {
                     client: client_id,
                     tx: tx_id,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
         state.accounts.insert(
-            client_id,
+            (client_id, None),
             ClientAccount {
                 client: client_id,
-                available: 10.0,
-                held: 0.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        let dispute_err = dispute.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let dispute_err = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
         assert_eq!(
             dispute_err,
             TxProcessingError::Dispute(ErrCause::ClientTxAlreadyInDispute)
@@ -84,7 +103,11 @@ mod tests {
 
     #[test]
     fn dispute_fails_if_client_in_does_not_match() {
-        let dispute = Dispute { client: 1, tx: 1 };
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -92,13 +115,18 @@ mod tests {
                 deposit: Deposit {
                     client: 2,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
 
-        let dispute_err = dispute.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let dispute_err = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
         assert_eq!(
             dispute_err,
             TxProcessingError::Dispute(ErrCause::ClientDidNotMatch)
@@ -108,7 +136,11 @@ mod tests {
     #[test]
     #[should_panic]
     fn dispute_panics_if_account_is_absent_and_diposit_is_present() {
-        let dispute = Dispute { client: 1, tx: 1 };
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -116,18 +148,27 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
 
-        dispute.process(&mut state, &TxProcessorImpl).unwrap_err();
+        dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
     }
 
     #[test]
     fn dispute_moves_the_deposit_amount_to_held() {
-        let dispute = Dispute { client: 1, tx: 1 };
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -135,31 +176,44 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
 
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        dispute.process(&mut state, &TxProcessorImpl).unwrap();
+        dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
-        assert_eq!(state.accounts.get(&1).unwrap().held, 20.0);
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().held,
+            "20.0".parse().unwrap()
+        );
     }
 
     #[test]
     fn dispute_marks_the_deposit_disputed() {
-        let dispute = Dispute { client: 1, tx: 1 };
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -167,25 +221,512 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
 
         state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(state.deposits.get(&1).unwrap().flag, Flag::Disputed);
+    }
+
+    #[test]
+    fn dispute_of_withdrawal_holds_the_withdrawn_amount() {
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.withdrawals.insert(
             1,
+            FlaggedWithdrawal {
+                withdrawal: Withdrawal {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::NotDisputed,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        dispute.process(&mut state, &TxProcessorImpl).unwrap();
+        dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap(),
+            &ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "20.0".parse().unwrap(),
+                total: "30.0".parse().unwrap(),
+                locked: false,
+            }
+        );
+        assert_eq!(state.withdrawals.get(&1).unwrap().flag, Flag::Disputed);
+    }
+
+    #[test]
+    fn dispute_fails_if_withdrawal_is_already_in_dispute() {
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.withdrawals.insert(
+            1,
+            FlaggedWithdrawal {
+                withdrawal: Withdrawal {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Disputed,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "20.0".parse().unwrap(),
+                total: "30.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        let dispute_err = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            dispute_err,
+            TxProcessingError::Dispute(ErrCause::ClientTxAlreadyInDispute)
+        )
+    }
+
+    #[test]
+    fn dispute_fails_if_deposit_was_charged_back() {
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.deposits.insert(
+            1,
+            FlaggedDeposit {
+                deposit: Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Chargebacked,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        let dispute_err = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            dispute_err,
+            TxProcessingError::Dispute(ErrCause::IllegalTxStateTransition)
+        );
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().held,
+            "0.0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn dispute_fails_if_deposit_was_resolved() {
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.deposits.insert(
+            1,
+            FlaggedDeposit {
+                deposit: Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Resolved,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        let dispute_err = dispute
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            dispute_err,
+            TxProcessingError::Dispute(ErrCause::IllegalTxStateTransition)
+        );
+    }
+
+    #[test]
+    fn resolved_deposit_can_be_disputed_again_if_policy_allows() {
+        let dispute = Dispute {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.deposits.insert(
+            1,
+            FlaggedDeposit {
+                deposit: Deposit {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Resolved,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        let processor = TxProcessorImpl::with_policy(DisputePolicy {
+            allow_redispute_after_resolve: true,
+            ..DisputePolicy::default()
+        });
+        dispute.process(&mut state, &processor).unwrap();
 
         assert_eq!(state.deposits.get(&1).unwrap().flag, Flag::Disputed);
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().held,
+            "20.0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn dispute_holds_funds_in_the_currency_of_the_deposit() {
+        let mut state = AppState::new();
+        Deposit {
+            client: 1,
+            tx: 1,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+        Deposit {
+            client: 1,
+            tx: 2,
+            amount: "20.0".parse().unwrap(),
+            currency: Some("EUR".to_string()),
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+
+        Dispute {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+
+        let eur = state.accounts.get(&(1, Some("EUR".to_string()))).unwrap();
+        assert_eq!(eur.held, "20.0".parse().unwrap());
+        assert_eq!(eur.available, "0.0".parse().unwrap());
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().held,
+            "0.0".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn dispute_is_refused_once_the_window_has_passed() {
+        let mut state = AppState::new();
+        let processor = TxProcessorImpl::with_policy(DisputePolicy {
+            dispute_window: Some(2),
+            ..DisputePolicy::default()
+        });
+        for tx in 1..=4 {
+            Deposit {
+                client: 1,
+                tx,
+                amount: "10.0".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            }
+            .process(&mut state, &processor)
+            .unwrap();
+        }
+
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 1,
+                timestamp: None
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::DisputeWindowExpired))
+        );
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 2,
+                timestamp: None
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::DisputeWindowExpired))
+        );
+        Dispute {
+            client: 1,
+            tx: 3,
+            timestamp: None,
+        }
+        .process(&mut state, &processor)
+        .unwrap();
+    }
+
+    #[test]
+    fn pruned_transactions_are_reported_as_expired() {
+        let mut state = AppState::new();
+        let processor = TxProcessorImpl::with_policy(DisputePolicy {
+            dispute_window: Some(2),
+            ..DisputePolicy::default()
+        });
+        for tx in 1..=6 {
+            Deposit {
+                client: 1,
+                tx,
+                amount: "10.0".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            }
+            .process(&mut state, &processor)
+            .unwrap();
+            if tx == 2 {
+                Dispute {
+                    client: 1,
+                    tx,
+                    timestamp: None,
+                }
+                .process(&mut state, &processor)
+                .unwrap();
+            }
+        }
+
+        let mut kept: Vec<_> = state.deposits.keys().copied().collect();
+        kept.sort_unstable();
+        assert_eq!(kept, vec![2, 5, 6]);
+        assert_eq!(state.tx_ids.len(), 6);
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 3,
+                timestamp: None
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::DisputeWindowExpired))
+        );
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 7,
+                timestamp: None
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::ClientTxNotFound))
+        );
+        Resolve {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        }
+        .process(&mut state, &processor)
+        .unwrap();
+    }
+
+    #[test]
+    fn disputes_have_to_be_within_both_windows() {
+        let mut state = AppState::new();
+        let processor = TxProcessorImpl::with_policy(DisputePolicy {
+            dispute_window: Some(3),
+            dispute_window_secs: Some(60),
+            ..DisputePolicy::default()
+        });
+        for (tx, timestamp) in [(1, 1_050), (2, 1_050), (3, 1_070), (4, 1_080)] {
+            Deposit {
+                client: 1,
+                tx,
+                amount: "10.0".parse().unwrap(),
+                currency: None,
+                timestamp: Some(timestamp),
+            }
+            .process(&mut state, &processor)
+            .unwrap();
+        }
+
+        // In time, but out of the count window.
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 1,
+                timestamp: Some(1_100)
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::DisputeWindowExpired))
+        );
+        // Within the count window, but too late.
+        assert_eq!(
+            Dispute {
+                client: 1,
+                tx: 2,
+                timestamp: Some(1_111)
+            }
+            .process(&mut state, &processor),
+            Err(TxProcessingError::Dispute(ErrCause::DisputeWindowExpired))
+        );
+        Dispute {
+            client: 1,
+            tx: 3,
+            timestamp: Some(1_111),
+        }
+        .process(&mut state, &processor)
+        .unwrap();
+        // Without a timestamp on the dispute, only the count window applies.
+        Dispute {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        }
+        .process(&mut state, &processor)
+        .unwrap();
+    }
+
+    #[test]
+    fn transfers_are_not_found_in_either_window_mode() {
+        for policy in [
+            DisputePolicy {
+                dispute_window: Some(2),
+                ..DisputePolicy::default()
+            },
+            DisputePolicy {
+                dispute_window_secs: Some(60),
+                ..DisputePolicy::default()
+            },
+        ] {
+            let mut state = AppState::new();
+            let processor = TxProcessorImpl::with_policy(policy);
+            Deposit {
+                client: 1,
+                tx: 1,
+                amount: "10.0".parse().unwrap(),
+                currency: None,
+                timestamp: Some(1_000),
+            }
+            .process(&mut state, &processor)
+            .unwrap();
+            Transfer {
+                client: 1,
+                destination: 2,
+                tx: 2,
+                amount: "1.0".parse().unwrap(),
+                currency: None,
+                timestamp: Some(1_000),
+            }
+            .process(&mut state, &processor)
+            .unwrap();
+
+            assert_eq!(
+                Dispute {
+                    client: 1,
+                    tx: 2,
+                    timestamp: Some(1_010)
+                }
+                .process(&mut state, &processor),
+                Err(TxProcessingError::Dispute(ErrCause::ClientTxNotFound)),
+                "{:?}",
+                policy
+            );
+        }
     }
 }
@@ -0,0 +1,232 @@
+use crate::account::ClientAccount;
+use crate::errors::EngineError;
+use crate::errors::TxProcessingError;
+use crate::processor::TxProcessorImpl;
+use crate::record::Record;
+use crate::state::AppState;
+use crate::storage::Storage;
+use crate::tx::Tx;
+
+/// Entry point for embedding the engine: owns the storage holding the
+/// application state and the processor that applies transactions to it.
+/// The state is kept in memory unless another [`Storage`] is given.
+#[derive(Debug)]
+pub struct Engine<S: Storage = AppState> {
+    storage: S,
+    processor: TxProcessorImpl,
+    position: u64,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_state(AppState::new())
+    }
+
+    pub fn with_state(state: AppState) -> Self {
+        Self::with_storage(state)
+    }
+
+    /// Account of `client` in the unnamed currency of single-currency input.
+    pub fn account(&self, client: u16) -> Option<&ClientAccount> {
+        self.storage.accounts.get(&(client, None))
+    }
+
+    pub fn currency_account(&self, client: u16, currency: &str) -> Option<&ClientAccount> {
+        self.storage
+            .accounts
+            .get(&(client, Some(currency.to_string())))
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &ClientAccount> {
+        self.storage.accounts.values()
+    }
+
+    pub fn state(&self) -> &AppState {
+        &self.storage
+    }
+
+    pub fn into_state(self) -> AppState {
+        self.storage
+    }
+}
+
+impl<S: Storage> Engine<S> {
+    pub fn with_storage(storage: S) -> Self {
+        Self {
+            storage,
+            processor: TxProcessorImpl::new(),
+            position: 0,
+        }
+    }
+
+    pub fn with_processor(mut self, processor: TxProcessorImpl) -> Self {
+        self.processor = processor;
+        self
+    }
+
+    /// Validates `record`, converts it to a transaction and applies it.
+    pub fn submit_record(&mut self, record: &Record) -> Result<(), EngineError> {
+        let tx = record
+            .to_tx()
+            .map_err(|err| EngineError::InvalidRecord(err.to_string()))?;
+        self.submit(tx.as_ref()).map_err(EngineError::Rejected)
+    }
+
+    /// Like [`Engine::submit_record`], recording `line` as the input position
+    /// in the journal and for later checkpoints.
+    pub fn submit_record_at(&mut self, line: u64, record: &Record) -> Result<(), EngineError> {
+        if let Some(journal) = self.processor.journal() {
+            journal.set_line(line);
+        }
+        self.position = line;
+        self.submit_record(record)
+    }
+
+    pub fn submit(&mut self, tx: &dyn Tx) -> Result<(), TxProcessingError> {
+        tx.process(&mut self.storage, &self.processor)
+    }
+
+    /// Input line of the last record passed to [`Engine::submit_record_at`].
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn processor(&self) -> &TxProcessorImpl {
+        &self.processor
+    }
+
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    pub fn into_storage(self) -> S {
+        self.storage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Engine;
+    use crate::deposit::Deposit;
+    use crate::errors::EngineError;
+    use crate::errors::ErrCause;
+    use crate::errors::TxProcessingError;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::record::Record;
+    use crate::state::OutOfOrderPolicy;
+
+    #[test]
+    fn submitted_records_update_accounts() {
+        let mut engine = Engine::new();
+        engine
+            .submit_record(&Record {
+                record_type: "deposit".to_string(),
+                client: 1,
+                tx: 1,
+                amount: Some("10.0".parse().unwrap()),
+                currency: None,
+                destination: None,
+                timestamp: None,
+            })
+            .unwrap();
+        engine
+            .submit_record(&Record {
+                record_type: "withdrawal".to_string(),
+                client: 1,
+                tx: 2,
+                amount: Some("2.5".parse().unwrap()),
+                currency: None,
+                destination: None,
+                timestamp: None,
+            })
+            .unwrap();
+
+        let account = engine.account(1).unwrap();
+        assert_eq!(account.available, "7.5".parse().unwrap());
+        assert_eq!(account.total, "7.5".parse().unwrap());
+        assert_eq!(engine.accounts().count(), 1);
+    }
+
+    #[test]
+    fn invalid_records_are_reported() {
+        let mut engine = Engine::new();
+        let err = engine
+            .submit_record(&Record {
+                record_type: "dispute".to_string(),
+                client: 1,
+                tx: 1,
+                amount: Some("10.0".parse().unwrap()),
+                currency: None,
+                destination: None,
+                timestamp: None,
+            })
+            .unwrap_err();
+
+        assert!(matches!(err, EngineError::InvalidRecord(_)));
+    }
+
+    #[test]
+    fn rejected_transactions_are_reported() {
+        let mut engine = Engine::new();
+        let err = engine
+            .submit(&Deposit {
+                client: 1,
+                tx: 1,
+                amount: "0".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            })
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            TxProcessingError::Deposit(ErrCause::AmountLessThanOrEqualToZero)
+        );
+        assert_eq!(engine.account(1), None);
+    }
+
+    #[test]
+    fn out_of_order_timestamps_are_rejected_per_client() {
+        let mut engine = Engine::new();
+        engine
+            .submit_record(&record("deposit", 1, 1, Some("10.0")).with_timestamp(200))
+            .unwrap();
+        assert_eq!(
+            engine.submit_record(&record("withdrawal", 1, 2, Some("1.0")).with_timestamp(100)),
+            Err(EngineError::Rejected(TxProcessingError::Withdrawal(
+                ErrCause::TimestampOutOfOrder
+            )))
+        );
+        engine
+            .submit_record(&record("withdrawal", 1, 3, Some("1.0")).with_timestamp(200))
+            .unwrap();
+        engine
+            .submit_record(&record("deposit", 2, 4, Some("1.0")).with_timestamp(100))
+            .unwrap();
+
+        assert_eq!(engine.state().timestamps.get(&1), Some(&200));
+        assert_eq!(engine.state().timestamps.get(&2), Some(&100));
+    }
+
+    #[test]
+    fn accepted_out_of_order_timestamps_keep_the_latest_one() {
+        let mut engine = Engine::new()
+            .with_processor(TxProcessorImpl::new().with_out_of_order(OutOfOrderPolicy::Accept));
+        engine
+            .submit_record(&record("deposit", 1, 1, Some("10.0")).with_timestamp(200))
+            .unwrap();
+        engine
+            .submit_record(&record("withdrawal", 1, 2, Some("1.0")).with_timestamp(100))
+            .unwrap();
+
+        assert_eq!(engine.account(1).unwrap().available, "9.0".parse().unwrap());
+        assert_eq!(engine.state().timestamps.get(&1), Some(&200));
+    }
+}
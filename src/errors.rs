@@ -1,7 +1,8 @@
+use serde::Serialize;
 use std::error::Error;
 use std::fmt::Display;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Serialize)]
 pub enum ErrCause {
     AmountLessThanOrEqualToZero,
     ClientAccountLocked,
@@ -11,19 +12,61 @@ pub enum ErrCause {
     ClientTxAlreadyInDispute,
     ClientTxIsNotUnderDispute,
     ClientDidNotMatch,
+    AmountOverflow,
+    DuplicateTransactionId,
+    IllegalTxStateTransition,
+    JournalWriteFailed,
+    AmountPrecisionExceeded,
+    StorageFailed,
+    DisputeWindowExpired,
+    TimestampOutOfOrder,
+    DestinationAccountLocked,
+    TransferToSelf,
+    TransferNeedsOneShard,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Serialize)]
 pub enum TxProcessingError {
     Deposit(ErrCause),
     Withdrawal(ErrCause),
     Dispute(ErrCause),
     Resolve(ErrCause),
     Chargeback(ErrCause),
+    Transfer(ErrCause),
+}
+
+impl TxProcessingError {
+    pub fn cause(&self) -> ErrCause {
+        match self {
+            TxProcessingError::Deposit(cause)
+            | TxProcessingError::Withdrawal(cause)
+            | TxProcessingError::Dispute(cause)
+            | TxProcessingError::Resolve(cause)
+            | TxProcessingError::Chargeback(cause)
+            | TxProcessingError::Transfer(cause) => *cause,
+        }
+    }
 }
 
 impl Error for TxProcessingError {}
 
+#[derive(Debug, PartialEq)]
+pub enum EngineError {
+    InvalidRecord(String),
+    Rejected(TxProcessingError),
+}
+
+impl Error for EngineError {}
+
+impl Display for EngineError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EngineError::InvalidRecord(reason) => write!(f, "{}", reason),
+            EngineError::Rejected(err) => write!(f, "{}", err),
+        }
+    }
+}
+
 impl Display for TxProcessingError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -36,6 +79,7 @@ impl Display for TxProcessingError {
             TxProcessingError::Chargeback(cause) => {
                 write!(f, "{}", msg("Chargeback", cause).as_str())
             }
+            TxProcessingError::Transfer(cause) => write!(f, "{}", msg("Transfer", cause).as_str()),
         }
     }
 }
@@ -49,9 +93,32 @@ fn msg(tag: &str, cause: &ErrCause) -> String {
         ErrCause::ClientTxNotFound => format!("{}: transaction not found", tag),
         ErrCause::ClientTxAlreadyInDispute => format!("{}: transaction is already in dispute", tag),
         ErrCause::ClientTxIsNotUnderDispute => format!("{}: transaction is not under dispute", tag),
+        ErrCause::AmountOverflow => format!("{}: amount overflow", tag),
+        ErrCause::DuplicateTransactionId => format!("{}: duplicate transaction id", tag),
+        ErrCause::AmountPrecisionExceeded => {
+            format!("{}: amount has more decimal places than the currency", tag)
+        }
+        ErrCause::JournalWriteFailed => format!("{}: could not write to the journal", tag),
+        ErrCause::DisputeWindowExpired => {
+            format!("{}: transaction is out of its dispute window", tag)
+        }
+        ErrCause::TimestampOutOfOrder => format!(
+            "{}: timestamp is earlier than the client's previous transaction",
+            tag
+        ),
+        ErrCause::DestinationAccountLocked => format!("{}: destination account locked", tag),
+        ErrCause::TransferToSelf => format!("{}: source and destination are the same client", tag),
+        ErrCause::TransferNeedsOneShard => format!(
+            "{}: transfers cannot be applied with more than one shard",
+            tag
+        ),
+        ErrCause::StorageFailed => format!("{}: could not read or write the storage", tag),
+        ErrCause::IllegalTxStateTransition => {
+            format!("{}: transaction can no longer be moved to this state", tag)
+        }
         ErrCause::ClientDidNotMatch => {
             format!(
-                "{}: client in dispute/resolve/chargeback does not match client in deposit",
+                "{}: client in dispute/resolve/chargeback does not match client of the disputed transaction",
                 tag
             )
         }
@@ -0,0 +1,34 @@
+use crate::account::ClientAccount;
+use crate::record::Record;
+use crate::state::AppState;
+
+/// A record without currency, destination or timestamp.
+pub fn record(record_type: &str, client: u16, tx: u32, amount: Option<&str>) -> Record {
+    Record::new(
+        record_type,
+        client,
+        tx,
+        amount.map(|amount| amount.parse().unwrap()),
+    )
+}
+
+/// An account in the unnamed currency with nothing held.
+pub fn account(client: u16, available: &str, locked: bool) -> ClientAccount {
+    ClientAccount {
+        client,
+        currency: None,
+        available: available.parse().unwrap(),
+        held: "0.0".parse().unwrap(),
+        total: available.parse().unwrap(),
+        locked,
+    }
+}
+
+/// A state holding `accounts` only.
+pub fn state(accounts: Vec<ClientAccount>) -> AppState {
+    let mut state = AppState::new();
+    for account in accounts {
+        state.accounts.insert(account.key(), account);
+    }
+    state
+}
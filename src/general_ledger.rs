@@ -0,0 +1,608 @@
+use crate::account::ClientAccount;
+use crate::amount::Amount;
+use crate::state::DisputableKind;
+use crate::storage::Update;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// The general ledger accounts the engine posts to.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum GlRole {
+    /// Funds clients can use; a liability to them.
+    ClientAvailable,
+    /// Client funds frozen by open disputes.
+    ClientHeld,
+    /// Cash moved through the settlement bank.
+    SettlementCash,
+    /// Funds returned to clients by withdrawal disputes and chargebacks,
+    /// which the platform carries until (if ever) it recovers them.
+    ChargebackLosses,
+}
+
+impl GlRole {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "client_available" => Ok(GlRole::ClientAvailable),
+            "client_held" => Ok(GlRole::ClientHeld),
+            "settlement_cash" => Ok(GlRole::SettlementCash),
+            "chargeback_losses" => Ok(GlRole::ChargebackLosses),
+            _ => Err(Box::<dyn Error>::from(format!(
+                "Unknown general ledger account: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Names of the general ledger accounts, e.g. their numbers in the finance
+/// team's chart of accounts.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ChartOfAccounts {
+    names: BTreeMap<GlRole, String>,
+}
+
+impl Default for ChartOfAccounts {
+    fn default() -> Self {
+        let mut names = BTreeMap::new();
+        names.insert(GlRole::SettlementCash, "1000 Settlement cash".to_string());
+        names.insert(GlRole::ClientAvailable, "2000 Client available".to_string());
+        names.insert(GlRole::ClientHeld, "2100 Client held".to_string());
+        names.insert(
+            GlRole::ChargebackLosses,
+            "6000 Chargeback losses".to_string(),
+        );
+        Self { names }
+    }
+}
+
+impl ChartOfAccounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_name(&mut self, role: GlRole, name: &str) {
+        self.names.insert(role, name.to_string());
+    }
+
+    /// Parses a `<role>=<name>` setting such as `settlement_cash=1010`.
+    pub fn parse_setting(&mut self, setting: &str) -> Result<(), Box<dyn Error>> {
+        let (role, name) = setting.split_once('=').ok_or_else(|| {
+            Box::<dyn Error>::from(format!(
+                "Invalid general ledger account setting {}, expected <account>=<name>",
+                setting
+            ))
+        })?;
+        self.set_name(GlRole::parse(role)?, name);
+        Ok(())
+    }
+
+    pub fn name(&self, role: GlRole) -> &str {
+        &self.names[&role]
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+/// The account outside the client accounts that a transaction moves funds
+/// to or from, and the side it is posted on; `disputed` is the kind of the
+/// transaction a dispute, resolve or chargeback refers to. Disputes and
+/// resolves of deposits, chargebacks of withdrawals and transfers only move
+/// funds between client accounts.
+pub fn external_leg(record_type: &str, disputed: Option<DisputableKind>) -> Option<(GlRole, Side)> {
+    use DisputableKind::Deposit;
+    use DisputableKind::Withdrawal;
+    use GlRole::*;
+
+    match (record_type, disputed) {
+        ("deposit", _) => Some((SettlementCash, Side::Debit)),
+        ("withdrawal", _) => Some((SettlementCash, Side::Credit)),
+        ("chargeback", Some(Deposit)) => Some((SettlementCash, Side::Credit)),
+        ("dispute", Some(Withdrawal)) => Some((ChargebackLosses, Side::Debit)),
+        ("resolve", Some(Withdrawal)) => Some((ChargebackLosses, Side::Credit)),
+        _ => None,
+    }
+}
+
+/// How a transaction changed the balances of one client account.
+#[derive(Debug, PartialEq, Clone)]
+pub struct BalanceChange {
+    pub client: u16,
+    pub available: Amount,
+    pub held: Amount,
+}
+
+impl BalanceChange {
+    /// The change from `before` (`None` for a new account) to `after`.
+    pub fn between(before: Option<&ClientAccount>, after: &ClientAccount) -> Self {
+        let (available, held) =
+            before.map_or((0, 0), |before| (before.available.raw(), before.held.raw()));
+        Self {
+            client: after.client,
+            available: Amount::from_raw(after.available.raw() - available),
+            held: Amount::from_raw(after.held.raw() - held),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize)]
+pub struct Leg {
+    pub account: String,
+    /// Client whose balance the leg reflects; the client of the transaction
+    /// for the external leg.
+    pub client: u16,
+    pub debit: Amount,
+    pub credit: Amount,
+}
+
+/// The legs one transaction posts to the general ledger.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Posting {
+    pub tx: u32,
+    pub client: u16,
+    pub record_type: &'static str,
+    pub currency: Option<String>,
+    pub legs: Vec<Leg>,
+}
+
+impl Posting {
+    pub fn is_balanced(&self) -> bool {
+        let sum = |amount: fn(&Leg) -> Amount| {
+            self.legs
+                .iter()
+                .try_fold(Amount::ZERO, |sum, leg| sum.checked_add(amount(leg)))
+        };
+        match (sum(|leg| leg.debit), sum(|leg| leg.credit)) {
+            (Some(debits), Some(credits)) => debits == credits,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PostingRow<'a> {
+    tx: u32,
+    client: u16,
+    #[serde(rename = "type")]
+    record_type: &'a str,
+    currency: Option<&'a str>,
+    account: &'a str,
+    debit: Amount,
+    credit: Amount,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TrialBalanceRow {
+    pub account: String,
+    pub currency: Option<String>,
+    pub debit: Amount,
+    pub credit: Amount,
+    pub balance: Amount,
+}
+
+#[derive(Default)]
+struct GeneralLedgerState {
+    postings: Option<csv::Writer<Box<dyn Write + Send>>>,
+    /// Debit and credit totals per account and currency.
+    totals: BTreeMap<(String, Option<String>), (Amount, Amount)>,
+    error: Option<String>,
+}
+
+/// Double-entry postings of every applied transaction, against a
+/// [`ChartOfAccounts`], with running totals for the trial balance.
+///
+/// Like the [`Ledger`](crate::ledger::Ledger), postings are made after the
+/// state has changed, so failures (an unbalanced posting, a failed write) are
+/// kept and reported by [`GeneralLedger::finish`]. Clones post to the same
+/// general ledger.
+#[derive(Clone)]
+pub struct GeneralLedger {
+    chart: ChartOfAccounts,
+    state: Arc<Mutex<GeneralLedgerState>>,
+}
+
+impl std::fmt::Debug for GeneralLedger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeneralLedger")
+            .field("chart", &self.chart)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GeneralLedger {
+    pub fn new(chart: ChartOfAccounts) -> Self {
+        Self {
+            chart,
+            state: Arc::new(Mutex::new(GeneralLedgerState::default())),
+        }
+    }
+
+    /// Also writes every leg to `writer` as CSV.
+    pub fn with_postings<W: Write + Send + 'static>(self, writer: W) -> Self {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        self.lock().postings = Some(
+            csv::WriterBuilder::new()
+                .has_headers(true)
+                .delimiter(b',')
+                .from_writer(writer),
+        );
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, GeneralLedgerState> {
+        match self.state.lock() {
+            Ok(state) => state,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+
+    /// Builds the posting of an applied `update` of `amount` from the
+    /// `changes` it made to client balances; `disputed` is as for
+    /// [`external_leg`]. Client balances are liabilities, so an increase is a
+    /// credit and a decrease a debit. The posting only balances if the
+    /// balances moved by exactly the amount the external leg posts.
+    pub fn posting(
+        &self,
+        record_type: &'static str,
+        disputed: Option<DisputableKind>,
+        amount: Amount,
+        update: &Update,
+        changes: &[BalanceChange],
+    ) -> Posting {
+        let client = update.account.client;
+        let mut legs = Vec::new();
+        let mut leg = |role: GlRole, client: u16, side: Side, amount: Amount| {
+            let (debit, credit) = match side {
+                Side::Debit => (amount, Amount::ZERO),
+                Side::Credit => (Amount::ZERO, amount),
+            };
+            legs.push(Leg {
+                account: self.chart.name(role).to_string(),
+                client,
+                debit,
+                credit,
+            });
+        };
+        if let Some((role, side)) = external_leg(record_type, disputed) {
+            leg(role, client, side, amount);
+        }
+        for change in changes {
+            for (role, delta) in [
+                (GlRole::ClientAvailable, change.available),
+                (GlRole::ClientHeld, change.held),
+            ] {
+                match delta.raw() {
+                    0 => {}
+                    raw if raw > 0 => leg(role, change.client, Side::Credit, delta),
+                    raw => leg(role, change.client, Side::Debit, Amount::from_raw(-raw)),
+                }
+            }
+        }
+        // Debits first, as journals are usually written.
+        legs.sort_by_key(|leg| leg.debit == Amount::ZERO);
+        Posting {
+            tx: update.tx_id(),
+            client,
+            record_type,
+            currency: update.account.currency.clone(),
+            legs,
+        }
+    }
+
+    pub fn post(&self, posting: &Posting) {
+        let mut state = self.lock();
+        if state.error.is_some() {
+            return;
+        }
+        if !posting.is_balanced() {
+            state.error = Some(format!("posting of tx {} does not balance", posting.tx));
+            return;
+        }
+
+        for leg in &posting.legs {
+            let totals = state
+                .totals
+                .entry((leg.account.clone(), posting.currency.clone()))
+                .or_insert((Amount::ZERO, Amount::ZERO));
+            match (
+                totals.0.checked_add(leg.debit),
+                totals.1.checked_add(leg.credit),
+            ) {
+                (Some(debit), Some(credit)) => *totals = (debit, credit),
+                _ => {
+                    state.error = Some(format!("totals of {} overflowed", leg.account));
+                    return;
+                }
+            }
+        }
+
+        if let Some(writer) = state.postings.as_mut() {
+            let written = posting.legs.iter().try_for_each(|leg| {
+                writer.serialize(PostingRow {
+                    tx: posting.tx,
+                    client: leg.client,
+                    record_type: posting.record_type,
+                    currency: posting.currency.as_deref(),
+                    account: &leg.account,
+                    debit: leg.debit,
+                    credit: leg.credit,
+                })
+            });
+            if let Err(err) = written {
+                state.error = Some(err.to_string());
+            }
+        }
+    }
+
+    /// Debit and credit totals and the resulting debit balance of every
+    /// account, per currency.
+    pub fn trial_balance(&self) -> Vec<TrialBalanceRow> {
+        self.lock()
+            .totals
+            .iter()
+            .map(|((account, currency), (debit, credit))| TrialBalanceRow {
+                account: account.clone(),
+                currency: currency.clone(),
+                debit: *debit,
+                credit: *credit,
+                balance: Amount::from_raw(debit.raw() - credit.raw()),
+            })
+            .collect()
+    }
+
+    /// Flushes the postings and reports the first failure, if any. Also
+    /// fails if the trial balance does not balance in some currency.
+    pub fn finish(&self) -> Result<Vec<TrialBalanceRow>, Box<dyn Error>> {
+        let mut state = self.lock();
+        if let Some(err) = &state.error {
+            return Err(Box::<dyn Error>::from(format!(
+                "General ledger failed: {}",
+                err
+            )));
+        }
+        if let Some(writer) = state.postings.as_mut() {
+            writer.flush()?;
+        }
+        drop(state);
+
+        let trial_balance = self.trial_balance();
+        let mut sums: BTreeMap<Option<String>, i128> = BTreeMap::new();
+        for row in &trial_balance {
+            *sums.entry(row.currency.clone()).or_insert(0) += row.balance.raw() as i128;
+        }
+        if let Some((currency, _)) = sums.iter().find(|(_, sum)| **sum != 0) {
+            return Err(Box::<dyn Error>::from(format!(
+                "Trial balance does not balance in {}",
+                currency.as_deref().unwrap_or("the unnamed currency")
+            )));
+        }
+        Ok(trial_balance)
+    }
+}
+
+/// Writes the trial balance as CSV.
+pub fn write_trial_balance<W: Write>(
+    writer: W,
+    rows: &[TrialBalanceRow],
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .delimiter(b',')
+        .from_writer(writer);
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::external_leg;
+    use super::BalanceChange;
+    use super::ChartOfAccounts;
+    use super::GeneralLedger;
+    use super::GlRole;
+    use super::Leg;
+    use super::Side;
+    use crate::account::ClientAccount;
+    use crate::amount::Amount;
+    use crate::deposit::Deposit;
+    use crate::engine::Engine;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::state::DisputableKind;
+    use crate::state::Flag;
+    use crate::state::FlaggedDeposit;
+    use crate::storage::TxChange;
+    use crate::storage::Update;
+
+    fn balance(gl: &GeneralLedger, account: &str) -> Amount {
+        gl.trial_balance()
+            .into_iter()
+            .find(|row| row.account == account)
+            .map_or(Amount::ZERO, |row| row.balance)
+    }
+
+    fn update(client: u16, counterpart: Option<u16>, tx: TxChange) -> Update {
+        let account = |client| ClientAccount {
+            client,
+            currency: None,
+            available: Amount::ZERO,
+            held: Amount::ZERO,
+            total: Amount::ZERO,
+            locked: false,
+        };
+        Update {
+            account: account(client),
+            counterpart: counterpart.map(account),
+            tx,
+            prune_before: None,
+            timestamp: None,
+            counterpart_timestamp: None,
+        }
+    }
+
+    fn change(client: u16, available: &str, held: &str) -> BalanceChange {
+        BalanceChange {
+            client,
+            available: available.parse().unwrap(),
+            held: held.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn only_funds_leaving_the_clients_have_an_external_leg() {
+        use DisputableKind::Deposit;
+        use DisputableKind::Withdrawal;
+
+        assert_eq!(
+            external_leg("deposit", None),
+            Some((GlRole::SettlementCash, Side::Debit))
+        );
+        assert_eq!(
+            external_leg("chargeback", Some(Deposit)),
+            Some((GlRole::SettlementCash, Side::Credit))
+        );
+        assert_eq!(
+            external_leg("dispute", Some(Withdrawal)),
+            Some((GlRole::ChargebackLosses, Side::Debit))
+        );
+        assert_eq!(external_leg("dispute", Some(Deposit)), None);
+        assert_eq!(external_leg("chargeback", Some(Withdrawal)), None);
+        assert_eq!(external_leg("transfer", None), None);
+    }
+
+    #[test]
+    fn transfers_move_funds_between_client_liabilities() {
+        let gl = GeneralLedger::new(ChartOfAccounts::new());
+        let posting = gl.posting(
+            "transfer",
+            None,
+            "4.0".parse().unwrap(),
+            &update(1, Some(2), TxChange::Transfer(7)),
+            &[change(1, "-4.0", "0"), change(2, "4.0", "0")],
+        );
+
+        assert!(posting.is_balanced());
+        assert_eq!(
+            posting.legs,
+            vec![
+                Leg {
+                    account: "2000 Client available".to_string(),
+                    client: 1,
+                    debit: "4.0".parse().unwrap(),
+                    credit: Amount::ZERO,
+                },
+                Leg {
+                    account: "2000 Client available".to_string(),
+                    client: 2,
+                    debit: Amount::ZERO,
+                    credit: "4.0".parse().unwrap(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn postings_that_do_not_balance_fail_the_run() {
+        let gl = GeneralLedger::new(ChartOfAccounts::new());
+        // The client was credited less than the cash that came in.
+        let deposit = Deposit {
+            client: 1,
+            tx: 3,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        };
+        let posting = gl.posting(
+            "deposit",
+            None,
+            deposit.amount,
+            &update(
+                1,
+                None,
+                TxChange::Deposit(FlaggedDeposit {
+                    deposit,
+                    flag: Flag::NotDisputed,
+                    seq: 0,
+                }),
+            ),
+            &[change(1, "9.0", "0")],
+        );
+        assert!(!posting.is_balanced());
+
+        gl.post(&posting);
+        assert_eq!(
+            gl.finish().unwrap_err().to_string(),
+            "General ledger failed: posting of tx 3 does not balance"
+        );
+    }
+
+    #[test]
+    fn client_accounts_mirror_engine_balances() {
+        let mut chart = ChartOfAccounts::new();
+        chart.parse_setting("settlement_cash=1010").unwrap();
+        let gl = GeneralLedger::new(chart);
+        let mut engine =
+            Engine::new().with_processor(TxProcessorImpl::new().with_general_ledger(gl.clone()));
+
+        for record in [
+            record("deposit", 1, 1, Some("10.0")),
+            record("deposit", 2, 2, Some("5.0")),
+            record("withdrawal", 1, 3, Some("4.0")),
+            record("dispute", 1, 1, None),
+            record("chargeback", 1, 1, None),
+            record("dispute", 2, 2, None),
+            record("resolve", 2, 2, None),
+            record("transfer", 2, 4, Some("2.0")).with_destination(3),
+        ] {
+            engine.submit_record(&record).unwrap();
+        }
+        gl.finish().unwrap();
+
+        // Liabilities have credit balances, hence the negated engine totals.
+        let available: i64 = engine.accounts().map(|a| a.available.raw()).sum();
+        let held: i64 = engine.accounts().map(|a| a.held.raw()).sum();
+        assert_eq!(balance(&gl, "2000 Client available").raw(), -available);
+        assert_eq!(balance(&gl, "2100 Client held").raw(), -held);
+        assert_eq!(balance(&gl, "1010"), "1.0".parse().unwrap());
+    }
+
+    #[test]
+    fn disputed_withdrawals_are_carried_as_chargeback_losses() {
+        let gl = GeneralLedger::new(ChartOfAccounts::new());
+        let mut engine =
+            Engine::new().with_processor(TxProcessorImpl::new().with_general_ledger(gl.clone()));
+        for record in [
+            record("deposit", 1, 1, Some("10.0")),
+            record("withdrawal", 1, 2, Some("4.0")),
+            record("dispute", 1, 2, None),
+            record("chargeback", 1, 2, None),
+        ] {
+            engine.submit_record(&record).unwrap();
+        }
+
+        let trial_balance = gl.finish().unwrap();
+        assert_eq!(
+            balance(&gl, "6000 Chargeback losses"),
+            "4.0".parse().unwrap()
+        );
+        assert_eq!(
+            balance(&gl, "2000 Client available").raw(),
+            -engine.account(1).unwrap().available.raw()
+        );
+        assert_eq!(trial_balance.len(), 4);
+        assert_eq!(
+            ChartOfAccounts::new().name(GlRole::ClientHeld),
+            "2100 Client held"
+        );
+    }
+}
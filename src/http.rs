@@ -0,0 +1,387 @@
+use crate::account::ClientAccount;
+use crate::currency::CurrencyConfig;
+use crate::errors::EngineError;
+use crate::errors::TxProcessingError;
+use crate::input::parse_json;
+use crate::output::write_account_json;
+use crate::shard::ShardHandle;
+use serde::Serialize;
+use std::error::Error;
+use std::io;
+use std::io::Read;
+use std::net::TcpListener;
+use std::thread;
+use std::vec;
+use tiny_http::Header;
+use tiny_http::Method;
+use tiny_http::Request;
+use tiny_http::Response;
+use tiny_http::StatusCode;
+
+/// Largest request body accepted for a transaction.
+const MAX_BODY: u64 = 64 * 1024;
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SubmitResponse {
+    Accepted,
+    Rejected {
+        error: TxProcessingError,
+        message: String,
+    },
+    Invalid {
+        message: String,
+    },
+}
+
+#[derive(Serialize)]
+struct ErrorResponse<'a> {
+    message: &'a str,
+}
+
+/// JSON API over a shared [`ShardHandle`], one thread per request.
+///
+/// - `POST /transactions` takes a JSON object with the fields of a
+///   [`Record`](crate::record::Record), read like a JSON Lines input row, and
+///   answers with a [`SubmitResponse`].
+/// - `GET /accounts/{client}` answers with the account of `client` in the
+///   unnamed currency, or in `?currency=<cur>`.
+/// - `GET /accounts` streams a JSON array of all accounts, by client id.
+///
+/// Accounts are written as in the `json` output format.
+#[derive(Clone)]
+pub struct HttpServer {
+    handle: ShardHandle,
+    currencies: CurrencyConfig,
+}
+
+impl HttpServer {
+    pub fn new(handle: ShardHandle) -> Self {
+        Self {
+            handle,
+            currencies: CurrencyConfig::new(),
+        }
+    }
+
+    /// Decimal places used for the amounts of each currency.
+    pub fn with_currencies(mut self, currencies: CurrencyConfig) -> Self {
+        self.currencies = currencies;
+        self
+    }
+
+    /// Serves requests on `listener` until it fails.
+    pub fn serve(&self, listener: TcpListener) -> Result<(), Box<dyn Error>> {
+        let server = tiny_http::Server::from_listener(listener, None)
+            .map_err(|err| Box::<dyn Error>::from(err.to_string()))?;
+        for request in server.incoming_requests() {
+            let server = self.clone();
+            thread::spawn(move || {
+                if let Err(err) = server.respond(request) {
+                    eprintln!("http: {}", err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn respond(&self, mut request: Request) -> Result<(), Box<dyn Error>> {
+        let url = request.url().to_string();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match (request.method(), segments.as_slice()) {
+            (Method::Post, ["transactions"]) => {
+                let response = self.submit(&mut request)?;
+                let status = match response {
+                    SubmitResponse::Accepted => 200,
+                    SubmitResponse::Rejected { .. } => 422,
+                    SubmitResponse::Invalid { .. } => 400,
+                };
+                request.respond(json(status, &response)?)?;
+            }
+            (Method::Get, ["accounts"]) => {
+                let mut accounts = self.handle.accounts()?;
+                accounts.sort_by_key(ClientAccount::key);
+                request.respond(Response::new(
+                    StatusCode(200),
+                    vec![json_header()],
+                    AccountsJson::new(accounts, self.currencies.clone()),
+                    None,
+                    None,
+                ))?;
+            }
+            (Method::Get, ["accounts", client]) => {
+                let currency = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("currency="));
+                let response = match client.parse::<u16>() {
+                    Err(_) => error(400, "invalid client id")?,
+                    Ok(client) => match self
+                        .handle
+                        .client_accounts(client)?
+                        .into_iter()
+                        .find(|account| account.currency.as_deref() == currency)
+                    {
+                        None => error(404, "account not found")?,
+                        Some(account) => {
+                            let mut body = Vec::new();
+                            write_account_json(&mut body, &account, &self.currencies)?;
+                            Response::from_data(body).with_header(json_header())
+                        }
+                    },
+                };
+                request.respond(response)?;
+            }
+            (_, ["transactions"]) | (_, ["accounts"]) | (_, ["accounts", _]) => {
+                request.respond(error(405, "method not allowed")?)?
+            }
+            _ => request.respond(error(404, "not found")?)?,
+        }
+        Ok(())
+    }
+
+    fn submit(&self, request: &mut Request) -> Result<SubmitResponse, Box<dyn Error>> {
+        let mut body = String::new();
+        request
+            .as_reader()
+            .take(MAX_BODY + 1)
+            .read_to_string(&mut body)?;
+        if body.len() as u64 > MAX_BODY {
+            return Ok(SubmitResponse::Invalid {
+                message: "request body too large".to_string(),
+            });
+        }
+
+        let record = match parse_json(&body) {
+            Ok(record) => record,
+            Err(message) => return Ok(SubmitResponse::Invalid { message }),
+        };
+        Ok(match self.handle.apply_record(record)? {
+            Ok(()) => SubmitResponse::Accepted,
+            Err(EngineError::Rejected(error)) => SubmitResponse::Rejected {
+                message: error.to_string(),
+                error,
+            },
+            Err(EngineError::InvalidRecord(message)) => SubmitResponse::Invalid { message },
+        })
+    }
+}
+
+fn json_header() -> Header {
+    Header::from_bytes("Content-Type", "application/json").expect("valid header")
+}
+
+fn json<T: Serialize>(
+    status: u16,
+    body: &T,
+) -> Result<Response<io::Cursor<Vec<u8>>>, Box<dyn Error>> {
+    Ok(Response::from_data(serde_json::to_vec(body)?)
+        .with_status_code(status)
+        .with_header(json_header()))
+}
+
+fn error(status: u16, message: &str) -> Result<Response<io::Cursor<Vec<u8>>>, Box<dyn Error>> {
+    json(status, &ErrorResponse { message })
+}
+
+/// Serializes accounts into a JSON array one account at a time, so the body
+/// is never held in memory as a whole.
+struct AccountsJson {
+    accounts: vec::IntoIter<ClientAccount>,
+    currencies: CurrencyConfig,
+    buf: Vec<u8>,
+    pos: usize,
+    started: bool,
+    done: bool,
+}
+
+impl AccountsJson {
+    fn new(accounts: Vec<ClientAccount>, currencies: CurrencyConfig) -> Self {
+        Self {
+            accounts: accounts.into_iter(),
+            currencies,
+            buf: Vec::new(),
+            pos: 0,
+            started: false,
+            done: false,
+        }
+    }
+
+    fn refill(&mut self) -> io::Result<()> {
+        self.buf.clear();
+        self.pos = 0;
+        match self.accounts.next() {
+            Some(account) => {
+                self.buf.push(if self.started { b',' } else { b'[' });
+                self.started = true;
+                write_account_json(&mut self.buf, &account, &self.currencies)?;
+            }
+            None => {
+                if !self.started {
+                    self.buf.push(b'[');
+                }
+                self.buf.push(b']');
+                self.done = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for AccountsJson {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.buf.len() {
+            if self.done {
+                return Ok(0);
+            }
+            self.refill()?;
+        }
+        let n = out.len().min(self.buf.len() - self.pos);
+        out[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HttpServer;
+    use crate::processor::TxProcessorImpl;
+    use crate::shard::ShardedEngine;
+    use crate::state::AppState;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn start_server() -> SocketAddr {
+        let engine = ShardedEngine::start(AppState::new(), 2, TxProcessorImpl::new);
+        let server = HttpServer::new(engine.handle());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener).unwrap());
+        addr
+    }
+
+    /// Sends one request and returns the status code and the body.
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head[9..12].parse().unwrap();
+        let body = if head
+            .to_ascii_lowercase()
+            .contains("transfer-encoding: chunked")
+        {
+            unchunk(body)
+        } else {
+            body.to_string()
+        };
+        (status, body)
+    }
+
+    fn unchunk(mut body: &str) -> String {
+        let mut out = String::new();
+        loop {
+            let (size, rest) = body.split_once("\r\n").unwrap();
+            let size = usize::from_str_radix(size, 16).unwrap();
+            if size == 0 {
+                return out;
+            }
+            out.push_str(&rest[..size]);
+            body = &rest[size + 2..];
+        }
+    }
+
+    #[test]
+    fn posted_transactions_are_applied() {
+        let addr = start_server();
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/transactions",
+            r#"{"type":"deposit","client":7,"tx":1,"amount":"10.5"}"#,
+        );
+        assert_eq!((status, body.as_str()), (200, r#"{"status":"accepted"}"#));
+        let (status, _) = request(
+            addr,
+            "POST",
+            "/transactions",
+            r#"{"type":"deposit","client":7,"tx":2,"amount":0.25}"#,
+        );
+        assert_eq!(status, 200);
+
+        let (status, body) = request(addr, "GET", "/accounts/7", "");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            r#"{"client":7,"available":10.7500,"held":0.0000,"total":10.7500,"locked":false}"#
+        );
+
+        let (_, accounts) = request(addr, "GET", "/accounts", "");
+        assert_eq!(accounts, format!("[{}]", body));
+    }
+
+    #[test]
+    fn rejections_are_returned_as_structured_errors() {
+        let addr = start_server();
+        let (status, body) = request(
+            addr,
+            "POST",
+            "/transactions",
+            r#"{"type":"withdrawal","client":1,"tx":1,"amount":"1.0"}"#,
+        );
+        assert_eq!(status, 422);
+        assert_eq!(
+            body,
+            r#"{"status":"rejected","error":{"Withdrawal":"ClientAccountNotFound"},"message":"Withdrawal: acount not found"}"#
+        );
+
+        let (status, body) = request(addr, "POST", "/transactions", r#"{"type":"deposit"}"#);
+        assert_eq!(status, 400);
+        assert!(body.starts_with(r#"{"status":"invalid","message":"missing field"#));
+    }
+
+    #[test]
+    fn all_accounts_are_streamed_as_a_json_array() {
+        let addr = start_server();
+        assert_eq!(
+            request(addr, "GET", "/accounts", ""),
+            (200, "[]".to_string())
+        );
+
+        for client in 1..=3 {
+            let body = format!(
+                r#"{{"type":"deposit","client":{},"tx":{},"amount":"1.0"}}"#,
+                client, client
+            );
+            assert_eq!(request(addr, "POST", "/transactions", &body).0, 200);
+        }
+        let (status, body) = request(addr, "GET", "/accounts", "");
+        let accounts: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert_eq!(status, 200);
+        assert_eq!(accounts.len(), 3);
+    }
+
+    #[test]
+    fn unknown_accounts_and_routes_are_not_found() {
+        let addr = start_server();
+        assert_eq!(request(addr, "GET", "/accounts/9", "").0, 404);
+        assert_eq!(request(addr, "GET", "/accounts/x", "").0, 400);
+        assert_eq!(request(addr, "GET", "/nothing", "").0, 404);
+        assert_eq!(request(addr, "DELETE", "/accounts", "").0, 405);
+    }
+}
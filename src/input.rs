@@ -0,0 +1,518 @@
+use crate::amount::Amount;
+use crate::compression::decompress;
+use crate::compression::uncompressed_path;
+use crate::compression::Compression;
+use crate::record::Record;
+use csv::ByteRecord;
+use csv::StringRecord;
+use csv::Trim;
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::vec;
+
+/// Path that stands for standard input.
+pub const STDIN: &str = "-";
+
+/// One data row of the input together with its line number and its text as
+/// it appeared in the file, so malformed rows can be reported verbatim.
+#[derive(Debug)]
+pub struct InputRow {
+    pub line: u64,
+    pub raw: String,
+    pub record: Result<Record, String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum InputFormat {
+    Csv,
+    JsonLines,
+}
+
+impl InputFormat {
+    /// `.jsonl` and `.ndjson` files are JSON Lines, anything else is CSV. A
+    /// compression extension is looked through, as in `.jsonl.gz`.
+    pub fn from_path(path: &Path) -> Self {
+        match uncompressed_path(path)
+            .extension()
+            .and_then(|ext| ext.to_str())
+        {
+            Some("jsonl") | Some("ndjson") => InputFormat::JsonLines,
+            _ => InputFormat::Csv,
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" | "ndjson" => Ok(InputFormat::JsonLines),
+            _ => Err(Box::<dyn Error>::from(format!(
+                "Unknown input format: {}",
+                name
+            ))),
+        }
+    }
+}
+
+pub type InputRows<'a> = Box<dyn Iterator<Item = Result<InputRow, Box<dyn Error>>> + 'a>;
+
+/// Reads the rows of `reader` in the given format.
+pub fn read_rows<'a, R: Read + 'a>(reader: R, format: InputFormat) -> InputRows<'a> {
+    match format {
+        InputFormat::Csv => Box::new(CsvRecords::new(reader)),
+        InputFormat::JsonLines => Box::new(JsonLinesRecords::new(BufReader::new(reader))),
+    }
+}
+
+/// Reads the rows of several inputs one after the other, `-` being stdin.
+/// Each file's format is taken from `format`, or else from its extension.
+/// Gzip and zstd compressed inputs are decompressed on the fly.
+///
+/// Line numbers continue across inputs as if they were one concatenated
+/// file, so they stay unique for rejections, journals and checkpoints.
+pub struct InputFiles {
+    paths: vec::IntoIter<PathBuf>,
+    format: Option<InputFormat>,
+    current: Option<InputRows<'static>>,
+    offset: u64,
+    last_line: u64,
+}
+
+impl InputFiles {
+    pub fn new(paths: Vec<PathBuf>, format: Option<InputFormat>) -> Self {
+        Self {
+            paths: paths.into_iter(),
+            format,
+            current: None,
+            offset: 0,
+            last_line: 0,
+        }
+    }
+
+    fn open(&self, path: &Path) -> Result<InputRows<'static>, Box<dyn Error>> {
+        let format = self.format.unwrap_or_else(|| InputFormat::from_path(path));
+        let compression = Compression::from_path(path);
+        if path == Path::new(STDIN) {
+            return Ok(read_rows(
+                decompress(io::stdin().lock(), compression)?,
+                format,
+            ));
+        }
+        Ok(read_rows(
+            decompress(File::open(path)?, compression)?,
+            format,
+        ))
+    }
+}
+
+impl Iterator for InputFiles {
+    type Item = Result<InputRow, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(rows) = self.current.as_mut() {
+                match rows.next() {
+                    Some(Ok(mut row)) => {
+                        row.line += self.offset;
+                        self.last_line = self.last_line.max(row.line);
+                        return Some(Ok(row));
+                    }
+                    Some(Err(err)) => return Some(Err(err)),
+                    None => {
+                        self.current = None;
+                        self.offset = self.last_line;
+                    }
+                }
+            }
+            let path = self.paths.next()?;
+            match self.open(&path) {
+                Ok(rows) => self.current = Some(rows),
+                Err(err) => {
+                    return Some(Err(Box::<dyn Error>::from(format!(
+                        "{}: {}",
+                        path.display(),
+                        err
+                    ))))
+                }
+            }
+        }
+    }
+}
+
+/// Reads transaction rows from CSV. Malformed rows are yielded with the parse
+/// error instead of ending the iteration; only I/O errors are returned as `Err`.
+pub struct CsvRecords<R: Read> {
+    rdr: csv::Reader<Recorded<R>>,
+    headers: Option<StringRecord>,
+    record: ByteRecord,
+    /// Bytes read from the input and not yet returned as a row.
+    seen: Rc<RefCell<Vec<u8>>>,
+    /// Input offset of the first byte of `seen`.
+    seen_from: u64,
+}
+
+impl<R: Read> CsvRecords<R> {
+    pub fn new(reader: R) -> Self {
+        let seen = Rc::new(RefCell::new(Vec::new()));
+        let reader = Recorded {
+            inner: reader,
+            seen: Rc::clone(&seen),
+        };
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .flexible(true)
+            .delimiter(b',')
+            .escape(None)
+            .trim(Trim::None)
+            .buffer_capacity(4098)
+            .from_reader(reader);
+        Self {
+            rdr,
+            headers: None,
+            record: ByteRecord::new(),
+            seen,
+            seen_from: 0,
+        }
+    }
+
+    /// The text of the row starting at input offset `start`, up to where the
+    /// reader stopped, without its line terminator.
+    fn raw(&mut self, start: u64) -> String {
+        let end = self.rdr.position().byte();
+        let mut seen = self.seen.borrow_mut();
+        let from = (start.saturating_sub(self.seen_from) as usize).min(seen.len());
+        let to = (end.saturating_sub(self.seen_from) as usize).clamp(from, seen.len());
+        let raw = String::from_utf8_lossy(&seen[from..to])
+            .trim_matches(['\r', '\n'])
+            .to_string();
+        seen.drain(..to);
+        self.seen_from += to as u64;
+        raw
+    }
+}
+
+/// Reader that keeps a copy of every byte read, so that rows can be reported
+/// exactly as they appeared in the input.
+struct Recorded<R: Read> {
+    inner: R,
+    seen: Rc<RefCell<Vec<u8>>>,
+}
+
+impl<R: Read> Read for Recorded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.seen.borrow_mut().extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+impl<R: Read> Iterator for CsvRecords<R> {
+    type Item = Result<InputRow, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.headers.is_none() {
+            // Columns are matched by name, so optional columns may be left out.
+            match self.rdr.headers() {
+                Ok(headers) => {
+                    let mut headers = headers.clone();
+                    headers.trim();
+                    self.headers = Some(headers);
+                }
+                Err(err) => return Some(Err(Box::new(err))),
+            }
+        }
+
+        match self.rdr.read_byte_record(&mut self.record) {
+            Ok(false) => None,
+            Ok(true) => {
+                let (line, start) = self
+                    .record
+                    .position()
+                    .map_or((0, 0), |position| (position.line(), position.byte()));
+                let raw = self.raw(start);
+                Some(Ok(InputRow {
+                    line,
+                    raw,
+                    record: parse(&self.record, self.headers.as_ref()),
+                }))
+            }
+            Err(err) if err.is_io_error() => Some(Err(Box::new(err))),
+            Err(err) => {
+                let (line, start) = err
+                    .position()
+                    .map_or((0, 0), |position| (position.line(), position.byte()));
+                Some(Ok(InputRow {
+                    line,
+                    raw: self.raw(start),
+                    record: Err(err.to_string()),
+                }))
+            }
+        }
+    }
+}
+
+fn parse(record: &ByteRecord, headers: Option<&StringRecord>) -> Result<Record, String> {
+    let mut record =
+        StringRecord::from_byte_record(record.clone()).map_err(|err| err.to_string())?;
+    record.trim();
+    record.deserialize(headers).map_err(|err| err.to_string())
+}
+
+/// Reads transaction rows from JSON Lines, one object with the fields of
+/// [`Record`] per line. Blank lines are skipped. Amounts may be JSON numbers
+/// or strings; either way their text is parsed exactly, without going through
+/// floating point.
+pub struct JsonLinesRecords<R: BufRead> {
+    reader: R,
+    line: u64,
+    buf: String,
+}
+
+impl<R: BufRead> JsonLinesRecords<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            line: 0,
+            buf: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLinesRecords<R> {
+    type Item = Result<InputRow, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(err) => return Some(Err(Box::new(err))),
+            }
+            self.line += 1;
+            let raw = self.buf.trim();
+            if raw.is_empty() {
+                continue;
+            }
+            return Some(Ok(InputRow {
+                line: self.line,
+                raw: raw.to_string(),
+                record: parse_json(raw),
+            }));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JsonRecord<'a> {
+    #[serde(rename = "type")]
+    record_type: String,
+    client: u16,
+    tx: u32,
+    #[serde(borrow, default)]
+    amount: Option<&'a RawValue>,
+    #[serde(default)]
+    currency: Option<String>,
+    #[serde(default)]
+    destination: Option<u16>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+/// Parses one JSON object into a record. Amounts may be numbers or strings.
+pub fn parse_json(line: &str) -> Result<Record, String> {
+    let record: JsonRecord = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let amount = match record.amount {
+        None => None,
+        Some(raw) => {
+            let text = raw.get();
+            let text = match text.strip_prefix('"').and_then(|t| t.strip_suffix('"')) {
+                Some(quoted) => quoted.trim(),
+                None => text,
+            };
+            Some(text.parse::<Amount>().map_err(|err| err.to_string())?)
+        }
+    };
+    Ok(Record {
+        record_type: record.record_type,
+        client: record.client,
+        tx: record.tx,
+        amount,
+        currency: record.currency,
+        destination: record.destination,
+        timestamp: record.timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CsvRecords;
+    use super::InputFiles;
+    use super::InputFormat;
+    use super::JsonLinesRecords;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn malformed_rows_are_yielded_with_their_error() {
+        let input = "type, client, tx, amount\n\
+                     deposit, 1, 1, 1.0\n\
+                     deposit, x, 2, 1.0\n\
+                     withdrawal, 1, 3, 0.5\n";
+        let rows: Vec<_> = CsvRecords::new(input.as_bytes())
+            .map(|row| row.unwrap())
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+        assert!(rows[0].record.is_ok());
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(rows[1].raw, "deposit, x, 2, 1.0");
+        assert!(rows[1].record.is_err());
+        assert_eq!(rows[2].record.as_ref().unwrap().tx, 3);
+    }
+
+    #[test]
+    fn malformed_rows_keep_their_quotes() {
+        let input = "type,client,tx,amount,currency\r\n\
+                     deposit,1,1,1.0,\"US,D\"\r\n\
+                     deposit,1,2,\"1,0\",\"say \"\"hi\"\"\"\r\n\
+                     \r\n\
+                     withdrawal,1,3,0.5,\n";
+        let rows: Vec<_> = CsvRecords::new(input.as_bytes())
+            .map(|row| row.unwrap())
+            .collect();
+
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].raw, "deposit,1,1,1.0,\"US,D\"");
+        assert!(rows[1].record.is_err());
+        assert_eq!(rows[1].raw, "deposit,1,2,\"1,0\",\"say \"\"hi\"\"\"");
+        assert_eq!(rows[2].raw, "withdrawal,1,3,0.5,");
+    }
+
+    #[test]
+    fn optional_columns_are_matched_by_header() {
+        let input = "type, client, tx, amount, currency\n\
+                     deposit, 1, 1, 1.0, EUR\n\
+                     deposit, 1, 2, 1.0,\n\
+                     dispute, 1, 1\n";
+        let rows: Vec<_> = CsvRecords::new(input.as_bytes())
+            .map(|row| row.unwrap().record.unwrap())
+            .collect();
+
+        assert_eq!(rows[0].currency, Some("EUR".to_string()));
+        assert_eq!(rows[1].currency, None);
+        assert_eq!(rows[2].amount, None);
+        assert_eq!(rows[2].currency, None);
+    }
+
+    #[test]
+    fn timestamps_are_read_when_present() {
+        let input = "type, client, tx, amount, timestamp\n\
+                     deposit, 1, 1, 1.0, 1700000000\n\
+                     dispute, 1, 1, ,\n";
+        let rows: Vec<_> = CsvRecords::new(input.as_bytes())
+            .map(|row| row.unwrap().record.unwrap())
+            .collect();
+        assert_eq!(rows[0].timestamp, Some(1_700_000_000));
+        assert_eq!(rows[1].timestamp, None);
+
+        let input = "{\"type\":\"resolve\",\"client\":1,\"tx\":1,\"timestamp\":1700000060}\n";
+        let rows: Vec<_> = JsonLinesRecords::new(input.as_bytes())
+            .map(|row| row.unwrap().record.unwrap())
+            .collect();
+        assert_eq!(rows[0].timestamp, Some(1_700_000_060));
+    }
+
+    #[test]
+    fn json_lines_rows_are_parsed_into_records() {
+        let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.5}\n\
+                     \n\
+                     {\"type\":\"deposit\",\"client\":1,\"tx\":2,\"amount\":\"2.25\",\"currency\":\"EUR\"}\n\
+                     {\"type\":\"dispute\",\"client\":1,\"tx\":1,\"amount\":null}\n\
+                     {\"type\":\"resolve\",\"client\":1,\"tx\":1}\n";
+        let rows: Vec<_> = JsonLinesRecords::new(input.as_bytes())
+            .map(|row| row.unwrap())
+            .collect();
+
+        assert_eq!(rows.len(), 4);
+        let deposit = rows[0].record.as_ref().unwrap();
+        assert_eq!(deposit.amount, Some("1.5".parse().unwrap()));
+        assert_eq!(rows[1].line, 3);
+        assert_eq!(
+            rows[1].record.as_ref().unwrap().currency,
+            Some("EUR".to_string())
+        );
+        assert_eq!(rows[2].record.as_ref().unwrap().amount, None);
+        assert_eq!(rows[3].record.as_ref().unwrap().amount, None);
+    }
+
+    #[test]
+    fn malformed_json_lines_are_yielded_with_their_error() {
+        let input = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":0.00001}\n\
+                     {\"type\":\"deposit\",\"client\":1\n\
+                     {\"type\":\"deposit\",\"client\":1,\"tx\":3,\"amount\":1e3}\n";
+        let rows: Vec<_> = JsonLinesRecords::new(input.as_bytes())
+            .map(|row| row.unwrap())
+            .collect();
+
+        assert!(rows.iter().all(|row| row.record.is_err()));
+        assert_eq!(rows[1].raw, "{\"type\":\"deposit\",\"client\":1");
+    }
+
+    #[test]
+    fn input_format_is_picked_from_extension() {
+        assert_eq!(
+            InputFormat::from_path(Path::new("events.jsonl")),
+            InputFormat::JsonLines
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("transactions.csv")),
+            InputFormat::Csv
+        );
+        assert_eq!(
+            InputFormat::from_path(Path::new("events.ndjson.gz")),
+            InputFormat::JsonLines
+        );
+    }
+
+    #[test]
+    fn line_numbers_continue_across_files() {
+        let dir = std::env::temp_dir();
+        let first = dir.join(format!("tx_engine_first_{}.csv", std::process::id()));
+        let second = dir.join(format!("tx_engine_second_{}.jsonl", std::process::id()));
+        fs::write(
+            &first,
+            "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,1.0\n",
+        )
+        .unwrap();
+        fs::write(&second, "{\"type\":\"dispute\",\"client\":1,\"tx\":1}\n").unwrap();
+
+        let rows: Vec<_> = InputFiles::new(vec![first.clone(), second.clone()], None)
+            .map(|row| row.unwrap())
+            .collect();
+        fs::remove_file(&first).unwrap();
+        fs::remove_file(&second).unwrap();
+
+        assert_eq!(
+            rows.iter().map(|row| row.line).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+        assert_eq!(rows[2].record.as_ref().unwrap().record_type, "dispute");
+    }
+
+    #[test]
+    fn missing_files_are_reported_by_name() {
+        let mut rows = InputFiles::new(vec!["does-not-exist.csv".into()], None);
+        let err = rows.next().unwrap().unwrap_err();
+        assert!(err.to_string().starts_with("does-not-exist.csv: "));
+    }
+}
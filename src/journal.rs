@@ -0,0 +1,334 @@
+use crate::engine::Engine;
+use crate::record::Record;
+use crate::storage::Storage;
+use serde::Deserialize;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub line: u64,
+    pub record: Record,
+    /// Cancels the entry just before it: the transaction passed validation
+    /// but the storage could not apply it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub void: bool,
+}
+
+/// Append-only write-ahead journal of accepted transactions, one JSON object
+/// per line. Each entry is flushed to the OS before the transaction touches
+/// the state, so a crashed run can be rebuilt with [`Journal::replay`]. Entries
+/// are only synced to disk by [`Journal::sync`], which callers do at
+/// checkpoints and at the end of a run.
+#[derive(Debug)]
+pub struct Journal {
+    writer: Mutex<BufWriter<File>>,
+    line: AtomicU64,
+}
+
+impl Journal {
+    /// Starts a new journal, discarding any previous content of `path`.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_file(File::create(path)?))
+    }
+
+    /// Opens `path` for appending, as when resuming a recovered run.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self::from_file(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ))
+    }
+
+    fn from_file(file: File) -> Self {
+        Self {
+            writer: Mutex::new(BufWriter::new(file)),
+            line: AtomicU64::new(0),
+        }
+    }
+
+    /// Sets the input line recorded with the following entries.
+    pub fn set_line(&self, line: u64) {
+        self.line.store(line, Ordering::Relaxed);
+    }
+
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        self.write(record, false)
+    }
+
+    /// Cancels the entry just appended for `record`, whose transaction could
+    /// not be applied after all.
+    pub fn void(&self, record: &Record) -> io::Result<()> {
+        self.write(record, true)
+    }
+
+    /// Flushes the journal and waits until its content is on disk.
+    pub fn sync(&self) -> io::Result<()> {
+        let mut writer = self.lock()?;
+        writer.flush()?;
+        writer.get_ref().sync_data()
+    }
+
+    fn write(&self, record: &Record, void: bool) -> io::Result<()> {
+        let entry = JournalEntryRef {
+            line: self.line.load(Ordering::Relaxed),
+            record,
+            void,
+        };
+        let mut writer = self.lock()?;
+        serde_json::to_writer(&mut *writer, &entry)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    fn lock(&self) -> io::Result<std::sync::MutexGuard<'_, BufWriter<File>>> {
+        self.writer
+            .lock()
+            .map_err(|_| io::Error::other("journal lock poisoned"))
+    }
+
+    /// Re-applies every entry recorded after input line `after_line` to
+    /// `engine` and returns the last input line found in the journal.
+    ///
+    /// A torn last entry, left behind by a crash in the middle of a write, is
+    /// cut off so the journal can be appended to again.
+    pub fn replay<S: Storage>(
+        path: &Path,
+        engine: &mut Engine<S>,
+        after_line: u64,
+    ) -> Result<u64, Box<dyn Error>> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let mut reader = BufReader::new(&file);
+        let mut buf = String::new();
+        let mut offset = 0;
+        let mut last_line = after_line;
+        // An entry is only applied once the next one is known not to void it.
+        let mut pending: Option<JournalEntry> = None;
+        let mut apply = |entry: JournalEntry| {
+            // The entry passed validation when it was written, so the only
+            // possible outcome here is the same as the original one.
+            let _result = engine.submit_record(&entry.record);
+        };
+
+        loop {
+            buf.clear();
+            let read = reader.read_line(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            match serde_json::from_str::<JournalEntry>(&buf) {
+                Ok(entry) if entry.line <= after_line => {}
+                Ok(entry) => {
+                    last_line = last_line.max(entry.line);
+                    if entry.void {
+                        if pending.as_ref().is_some_and(|voided| {
+                            voided.line == entry.line && voided.record == entry.record
+                        }) {
+                            pending = None;
+                        }
+                    } else if let Some(previous) = pending.replace(entry) {
+                        apply(previous);
+                    }
+                }
+                Err(err) => {
+                    if reader.fill_buf()?.is_empty() {
+                        file.set_len(offset)?;
+                        break;
+                    }
+                    return Err(Box::<dyn Error>::from(format!(
+                        "Corrupt journal entry at byte {}: {}",
+                        offset, err
+                    )));
+                }
+            }
+            offset += read as u64;
+        }
+        if let Some(entry) = pending {
+            apply(entry);
+        }
+
+        Ok(last_line)
+    }
+}
+
+#[derive(Serialize)]
+struct JournalEntryRef<'a> {
+    line: u64,
+    record: &'a Record,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    void: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Journal;
+    use crate::account::AccountKey;
+    use crate::account::ClientAccount;
+    use crate::engine::Engine;
+    use crate::errors::EngineError;
+    use crate::errors::ErrCause;
+    use crate::errors::TxProcessingError;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::state::AppState;
+    use crate::state::DisputableTx;
+    use crate::storage::Storage;
+    use crate::storage::StorageError;
+    use crate::storage::Update;
+    use std::fs;
+    use std::io::Write;
+    use std::path::Path;
+    use std::path::PathBuf;
+
+    fn journal_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tx_engine_{}_{}.journal", name, std::process::id()))
+    }
+
+    fn journaled_engine(path: &Path) -> Engine {
+        Engine::new()
+            .with_processor(TxProcessorImpl::new().with_journal(Journal::create(path).unwrap()))
+    }
+
+    #[test]
+    fn only_accepted_transactions_are_journaled() {
+        let path = journal_path("accepted");
+        let mut engine = journaled_engine(&path);
+        engine
+            .submit_record_at(2, &record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        engine
+            .submit_record_at(3, &record("withdrawal", 1, 2, Some("50.0")))
+            .unwrap_err();
+        engine
+            .submit_record_at(4, &record("dispute", 1, 1, None))
+            .unwrap();
+
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            content,
+            "{\"line\":2,\"record\":{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"10.0000\"}}\n\
+             {\"line\":4,\"record\":{\"type\":\"dispute\",\"client\":1,\"tx\":1,\"amount\":null}}\n"
+        );
+    }
+
+    #[test]
+    fn replay_rebuilds_state_after_the_given_line() {
+        let path = journal_path("replay");
+        let mut engine = journaled_engine(&path);
+        engine
+            .submit_record_at(2, &record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        engine
+            .submit_record_at(3, &record("deposit", 1, 2, Some("5.0")))
+            .unwrap();
+        engine
+            .submit_record_at(5, &record("dispute", 1, 2, None))
+            .unwrap();
+
+        let mut recovered = Engine::new();
+        let last_line = Journal::replay(&path, &mut recovered, 0).unwrap();
+        assert_eq!(last_line, 5);
+        assert_eq!(recovered.account(1), engine.account(1));
+
+        let mut tail = Engine::new();
+        Journal::replay(&path, &mut tail, 2).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tail.account(1).unwrap().held, "5.0".parse().unwrap());
+        assert_eq!(tail.account(1).unwrap().total, "5.0".parse().unwrap());
+    }
+
+    #[test]
+    fn replay_cuts_off_a_torn_last_entry() {
+        let path = journal_path("torn");
+        let mut engine = journaled_engine(&path);
+        engine
+            .submit_record_at(2, &record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        let intact = fs::read_to_string(&path).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"line\":3,\"rec")
+            .unwrap();
+
+        let mut recovered = Engine::new();
+        let last_line = Journal::replay(&path, &mut recovered, 0).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(last_line, 2);
+        assert_eq!(content, intact);
+    }
+
+    /// Storage whose writes all fail.
+    struct FailingStorage(AppState);
+
+    impl Storage for FailingStorage {
+        fn account(&self, key: &AccountKey) -> Result<Option<ClientAccount>, StorageError> {
+            self.0.account(key)
+        }
+
+        fn is_known_tx(&self, tx: u32) -> Result<bool, StorageError> {
+            self.0.is_known_tx(tx)
+        }
+
+        fn is_transfer(&self, tx: u32) -> Result<bool, StorageError> {
+            self.0.is_transfer(tx)
+        }
+
+        fn accepted(&self) -> Result<u64, StorageError> {
+            self.0.accepted()
+        }
+
+        fn disputable(&self, tx: u32) -> Result<Option<DisputableTx>, StorageError> {
+            self.0.disputable(tx)
+        }
+
+        fn last_timestamp(&self, client: u16) -> Result<Option<u64>, StorageError> {
+            self.0.last_timestamp(client)
+        }
+
+        fn apply(&mut self, _update: &Update) -> Result<(), StorageError> {
+            Err(StorageError("disk full".to_string()))
+        }
+
+        fn accounts(&self) -> Result<Vec<ClientAccount>, StorageError> {
+            self.0.accounts()
+        }
+    }
+
+    #[test]
+    fn entries_the_storage_could_not_apply_are_not_replayed() {
+        let path = journal_path("void");
+        let mut engine = Engine::with_storage(FailingStorage(AppState::new()))
+            .with_processor(TxProcessorImpl::new().with_journal(Journal::create(&path).unwrap()));
+        assert_eq!(
+            engine.submit_record_at(2, &record("deposit", 1, 1, Some("10.0"))),
+            Err(EngineError::Rejected(TxProcessingError::Deposit(
+                ErrCause::StorageFailed
+            )))
+        );
+        engine.processor().journal().unwrap().sync().unwrap();
+
+        let mut recovered = Engine::new();
+        let last_line = Journal::replay(&path, &mut recovered, 0).unwrap();
+        let content = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(last_line, 2);
+        assert_eq!(recovered.account(1), None);
+        assert!(content.ends_with("\"void\":true}\n"));
+    }
+}
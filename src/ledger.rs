@@ -0,0 +1,181 @@
+use crate::account::ClientAccount;
+use crate::amount::Amount;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// One applied transaction and the balances it left the account with.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct LedgerEntry {
+    pub client: u16,
+    pub tx: u32,
+    #[serde(rename = "type")]
+    pub record_type: &'static str,
+    pub amount: Amount,
+    pub currency: Option<String>,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+    pub timestamp: Option<u64>,
+}
+
+impl LedgerEntry {
+    pub fn new(
+        record_type: &'static str,
+        tx: u32,
+        amount: Amount,
+        timestamp: Option<u64>,
+        account: &ClientAccount,
+    ) -> Self {
+        Self {
+            client: account.client,
+            tx,
+            record_type,
+            amount,
+            currency: account.currency.clone(),
+            available: account.available,
+            held: account.held,
+            total: account.total,
+            locked: account.locked,
+            timestamp,
+        }
+    }
+}
+
+struct LedgerWriter {
+    writer: csv::Writer<Box<dyn Write + Send>>,
+    error: Option<String>,
+}
+
+/// CSV ledger of every applied transaction, in the order they were applied.
+/// Dispute, resolve and chargeback entries carry the amount of the disputed
+/// transaction.
+///
+/// Entries are written after the state has changed, so a failed write cannot
+/// reject the transaction; the first failure is kept and returned by
+/// [`Ledger::finish`]. Clones write to the same ledger.
+#[derive(Clone)]
+pub struct Ledger {
+    inner: Arc<Mutex<LedgerWriter>>,
+}
+
+impl std::fmt::Debug for Ledger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ledger").finish_non_exhaustive()
+    }
+}
+
+impl Ledger {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        let writer: Box<dyn Write + Send> = Box::new(writer);
+        Self {
+            inner: Arc::new(Mutex::new(LedgerWriter {
+                writer: csv::WriterBuilder::new()
+                    .has_headers(true)
+                    .delimiter(b',')
+                    .from_writer(writer),
+                error: None,
+            })),
+        }
+    }
+
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    pub fn record(&self, entry: &LedgerEntry) {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if inner.error.is_none() {
+            if let Err(err) = inner.writer.serialize(entry) {
+                inner.error = Some(err.to_string());
+            }
+        }
+    }
+
+    /// Flushes the ledger and reports the first write that failed, if any.
+    pub fn finish(&self) -> Result<(), Box<dyn Error>> {
+        let mut inner = match self.inner.lock() {
+            Ok(inner) => inner,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(err) = &inner.error {
+            return Err(Box::<dyn Error>::from(format!(
+                "Could not write ledger: {}",
+                err
+            )));
+        }
+        inner.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ledger;
+    use crate::engine::Engine;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use std::io;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    /// Writer whose content can still be read after the ledger took it.
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn applied_transactions_are_written_with_resulting_balances() {
+        let buf = SharedBuf::default();
+        let ledger = Ledger::new(buf.clone());
+        let mut engine =
+            Engine::new().with_processor(TxProcessorImpl::new().with_ledger(ledger.clone()));
+
+        engine
+            .submit_record(&record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        engine
+            .submit_record(&record("withdrawal", 1, 2, Some("20.0")))
+            .unwrap_err();
+        engine
+            .submit_record(&record("withdrawal", 1, 3, Some("4.0")))
+            .unwrap();
+        engine
+            .submit_record(&record("dispute", 1, 1, None))
+            .unwrap();
+        engine
+            .submit_record(&record("chargeback", 1, 1, None))
+            .unwrap();
+        ledger.finish().unwrap();
+
+        let written = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(
+            written,
+            "client,tx,type,amount,currency,available,held,total,locked,timestamp\n\
+             1,1,deposit,10.0000,,10.0000,0.0000,10.0000,false,\n\
+             1,3,withdrawal,4.0000,,6.0000,0.0000,6.0000,false,\n\
+             1,1,dispute,10.0000,,-4.0000,10.0000,6.0000,false,\n\
+             1,1,chargeback,10.0000,,-4.0000,0.0000,-4.0000,true,\n"
+        );
+    }
+}
@@ -0,0 +1,39 @@
+pub mod account;
+pub mod amount;
+pub mod chargeback;
+pub mod compression;
+pub mod currency;
+pub mod deposit;
+pub mod dispute;
+pub mod engine;
+pub mod errors;
+#[cfg(test)]
+mod fixtures;
+pub mod general_ledger;
+pub mod http;
+pub mod input;
+pub mod journal;
+pub mod ledger;
+pub mod output;
+pub mod processor;
+pub mod quarantine;
+pub mod record;
+pub mod rejections;
+pub mod resolve;
+pub mod server;
+pub mod shard;
+pub mod snapshot;
+pub mod spill;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod state;
+pub mod storage;
+pub mod transfer;
+pub mod tx;
+pub mod withdrawal;
+
+pub use crate::engine::Engine;
+
+#[cfg(doctest)]
+#[doc = include_str!("../README.md")]
+struct ReadmeDoctests;
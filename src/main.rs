@@ -1,62 +1,408 @@
-use crate::processor::TxProcessorImpl;
-use crate::record::Record;
-use crate::state::AppState;
-use csv::Trim;
+use crate::cli::Command;
+use crate::cli::Options;
+use crate::cli::ParseMode;
 use std::env::args;
 use std::error::Error;
+use std::fs::File;
 use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::net::TcpListener;
 use std::path::PathBuf;
+use tx_engine::account::ClientAccount;
+use tx_engine::errors::EngineError;
+use tx_engine::general_ledger::write_trial_balance;
+use tx_engine::general_ledger::GeneralLedger;
+use tx_engine::http::HttpServer;
+use tx_engine::input::InputFiles;
+use tx_engine::input::InputRows;
+use tx_engine::journal::Journal;
+use tx_engine::ledger::Ledger;
+use tx_engine::output::sort_accounts;
+use tx_engine::output::write_accounts;
+use tx_engine::processor::TxProcessorImpl;
+use tx_engine::quarantine::QuarantineSink;
+use tx_engine::quarantine::QuarantinedRow;
+use tx_engine::record::Record;
+use tx_engine::rejections::Rejection;
+use tx_engine::rejections::RejectionFormat;
+use tx_engine::rejections::RejectionSink;
+use tx_engine::server::Server;
+use tx_engine::shard::ShardedEngine;
+use tx_engine::snapshot::Snapshot;
+use tx_engine::spill::SpillingStorage;
+#[cfg(feature = "sqlite")]
+use tx_engine::sqlite::SqliteStorage;
+use tx_engine::state::AppState;
+use tx_engine::state::DisputePolicy;
+use tx_engine::storage::Storage;
+use tx_engine::Engine;
 
-pub mod account;
-pub mod chargeback;
-pub mod deposit;
-pub mod dispute;
-pub mod errors;
-pub mod processor;
-pub mod record;
-pub mod resolve;
-pub mod state;
-pub mod tx;
-pub mod withdrawal;
+mod cli;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let tx_file =
-        match args().nth(1) {
-            None => return Err(Box::<dyn Error>::from(
-                "Usage: tx_engine <transactions csv file> or cargo run -- <transactions csv file>",
-            )),
-            Some(file) => PathBuf::from(file),
+    let options = Options::parse(args().skip(1))?;
+    match &options.command {
+        Command::Process(tx_files) => process_tx_files(&options, tx_files),
+        Command::Serve(address) | Command::Http(address) => serve(&options, address),
+    }
+}
+
+fn serve(options: &Options, address: &str) -> Result<(), Box<dyn Error>> {
+    let (state, _) = initial_state(options)?;
+    let engine = ShardedEngine::start(state, options.shards, || processor(options));
+    let listener = TcpListener::bind(address)?;
+    writeln!(io::stderr(), "listening on {}", listener.local_addr()?)?;
+    match options.command {
+        Command::Http(_) => HttpServer::new(engine.handle())
+            .with_currencies(options.currencies.clone())
+            .serve(listener),
+        _ => Ok(Server::new(engine.handle())
+            .with_currencies(options.currencies.clone())
+            .serve(listener)?),
+    }
+}
+
+/// Rejection, quarantine and ledger outputs shared by the sequential and the
+/// sharded run.
+struct Outputs {
+    rejections: Option<RejectionSink<BufWriter<File>>>,
+    quarantine: Option<QuarantineSink<BufWriter<File>>>,
+    ledger: Option<Ledger>,
+    general_ledger: Option<GeneralLedger>,
+    trial_balance: Option<PathBuf>,
+}
+
+impl Outputs {
+    fn create(options: &Options) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            rejections: match &options.rejections {
+                None => None,
+                Some(path) => Some(RejectionSink::new(
+                    RejectionFormat::from_path(path),
+                    BufWriter::new(File::create(path)?),
+                )),
+            },
+            quarantine: match &options.quarantine {
+                None => None,
+                Some(path) => Some(QuarantineSink::new(BufWriter::new(File::create(path)?))),
+            },
+            ledger: match &options.ledger {
+                None => None,
+                Some(path) => Some(Ledger::create(path)?),
+            },
+            general_ledger: match (&options.gl_postings, &options.trial_balance) {
+                (None, None) => None,
+                (None, Some(_)) => Some(GeneralLedger::new(options.chart.clone())),
+                (Some(path), _) => Some(
+                    GeneralLedger::new(options.chart.clone())
+                        .with_postings(BufWriter::new(File::create(path)?)),
+                ),
+            },
+            trial_balance: options.trial_balance.clone(),
+        })
+    }
+
+    /// The processor for the run, writing to the ledgers if there are any.
+    fn processor(&self, options: &Options) -> TxProcessorImpl {
+        let mut processor = processor(options);
+        if let Some(ledger) = &self.ledger {
+            processor = processor.with_ledger(ledger.clone());
+        }
+        if let Some(general_ledger) = &self.general_ledger {
+            processor = processor.with_general_ledger(general_ledger.clone());
+        }
+        processor
+    }
+
+    fn handle(
+        &mut self,
+        options: &Options,
+        line: u64,
+        raw: &str,
+        record: Option<&Record>,
+        err: EngineError,
+    ) -> Result<(), Box<dyn Error>> {
+        match err {
+            EngineError::Rejected(err) => {
+                if let (Some(sink), Some(record)) = (self.rejections.as_mut(), record) {
+                    sink.record(&Rejection::new(line, record, &err))?;
+                }
+                Ok(())
+            }
+            EngineError::InvalidRecord(reason) => match options.mode {
+                ParseMode::Strict => {
+                    Err(Box::<dyn Error>::from(format!("line {}: {}", line, reason)))
+                }
+                ParseMode::Lenient => {
+                    if let Some(sink) = self.quarantine.as_mut() {
+                        sink.record(&QuarantinedRow {
+                            line,
+                            error: &reason,
+                            row: raw,
+                        })?;
+                    }
+                    Ok(())
+                }
+            },
+        }
+    }
+
+    fn finish(self) -> Result<(), Box<dyn Error>> {
+        if let Some(mut sink) = self.rejections {
+            sink.flush()?;
+            write_rejection_summary(&sink)?;
+        }
+        if let Some(mut sink) = self.quarantine {
+            sink.flush()?;
+            writeln!(io::stderr(), "quarantined rows: {}", sink.count())?;
+        }
+        if let Some(ledger) = self.ledger {
+            ledger.finish()?;
+        }
+        if let Some(general_ledger) = self.general_ledger {
+            let trial_balance = general_ledger.finish()?;
+            if let Some(path) = &self.trial_balance {
+                write_trial_balance(BufWriter::new(File::create(path)?), &trial_balance)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn process_tx_files(options: &Options, tx_files: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    let rows = Box::new(InputFiles::new(tx_files.to_vec(), options.input_format));
+    let mut outputs = Outputs::create(options)?;
+
+    #[cfg(feature = "sqlite")]
+    if let Some(path) = &options.storage {
+        let storage = SqliteStorage::open(path)?;
+        return process_stored(options, rows, outputs, storage);
+    }
+    if let Some(dir) = &options.spill {
+        let storage = SpillingStorage::create(dir, options.spill_after)?;
+        return process_stored(options, rows, outputs, storage);
+    }
+
+    let state = match options.shards {
+        1 => process_sequential(options, rows, &mut outputs)?,
+        _ => process_sharded(options, rows, &mut outputs)?,
+    };
+
+    print_accounts(options, state.accounts.values().collect())?;
+
+    if let Some(path) = &options.snapshot_out {
+        Snapshot::from_state(&state).save(path)?;
+    }
+
+    outputs.finish()
+}
+
+fn print_accounts(
+    options: &Options,
+    mut accounts: Vec<&ClientAccount>,
+) -> Result<(), Box<dyn Error>> {
+    sort_accounts(&mut accounts, options.sort);
+    write_accounts(
+        io::stdout().lock(),
+        &accounts,
+        &options.currencies,
+        options.output_format,
+    )
+}
+
+fn process_sequential(
+    options: &Options,
+    rows: InputRows,
+    outputs: &mut Outputs,
+) -> Result<AppState, Box<dyn Error>> {
+    let (state, resume_after) = initial_state(options)?;
+    let processor = journaled_processor(options, outputs)?;
+    let mut engine = Engine::with_state(state).with_processor(processor);
+    let mut rows_since_checkpoint = 0;
+
+    apply_rows(
+        options,
+        rows,
+        outputs,
+        &mut engine,
+        resume_after,
+        |engine, line| {
+            if let Some(path) = &options.checkpoint {
+                rows_since_checkpoint += 1;
+                if rows_since_checkpoint == options.checkpoint_every {
+                    sync_journal(engine)?;
+                    Snapshot::at_position(engine.state(), line).save(path)?;
+                    rows_since_checkpoint = 0;
+                }
+            }
+            Ok(())
+        },
+    )?;
+
+    Ok(engine.into_state())
+}
+
+/// Applies the input to the state kept in `storage` (`--storage` or
+/// `--spill`) and prints the accounts it holds afterwards.
+fn process_stored<S: Storage>(
+    options: &Options,
+    rows: InputRows,
+    mut outputs: Outputs,
+    storage: S,
+) -> Result<(), Box<dyn Error>> {
+    let processor = journaled_processor(options, &outputs)?;
+    let mut engine = Engine::with_storage(storage).with_processor(processor);
+    apply_rows(options, rows, &mut outputs, &mut engine, 0, |_, _| Ok(()))?;
+    let accounts = engine.storage().accounts()?;
+    print_accounts(options, accounts.iter().collect())?;
+    outputs.finish()
+}
+
+fn journaled_processor(
+    options: &Options,
+    outputs: &Outputs,
+) -> Result<TxProcessorImpl, Box<dyn Error>> {
+    let mut processor = outputs.processor(options);
+    if let Some(path) = &options.journal {
+        processor = processor.with_journal(match options.recover {
+            true => Journal::open(path)?,
+            false => Journal::create(path)?,
+        });
+    }
+    Ok(processor)
+}
+
+/// Submits every row after input line `resume_after` to `engine` on this
+/// thread, calling `after_row` with the line of each row once it is handled.
+fn apply_rows<S: Storage>(
+    options: &Options,
+    rows: InputRows,
+    outputs: &mut Outputs,
+    engine: &mut Engine<S>,
+    resume_after: u64,
+    mut after_row: impl FnMut(&Engine<S>, u64) -> Result<(), Box<dyn Error>>,
+) -> Result<(), Box<dyn Error>> {
+    for row in rows {
+        let row = row?;
+        if row.line <= resume_after {
+            continue;
+        }
+        let result = match &row.record {
+            Ok(record) => engine.submit_record_at(row.line, record),
+            Err(reason) => Err(EngineError::InvalidRecord(reason.clone())),
         };
-    process_tx_file(&tx_file)
+        if let Err(err) = result {
+            outputs.handle(options, row.line, &row.raw, row.record.as_ref().ok(), err)?;
+        }
+        after_row(engine, row.line)?;
+    }
+    sync_journal(engine)
+}
+
+/// Makes sure every journal entry written so far survives a crash.
+fn sync_journal<S: Storage>(engine: &Engine<S>) -> Result<(), Box<dyn Error>> {
+    if let Some(journal) = engine.processor().journal() {
+        journal.sync()?;
+    }
+    Ok(())
 }
 
-fn process_tx_file(file: &PathBuf) -> Result<(), Box<dyn Error>> {
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .flexible(true)
-        .delimiter(b',')
-        .escape(None)
-        .trim(Trim::All)
-        .buffer_capacity(4098)
-        .from_path(file)?;
+/// Parses the input on this thread and applies it on `--shards` workers.
+/// Malformed rows are caught here; rejections are reported once all shards
+/// are done, in input order. A transfer fails the run, as the shards cannot
+/// apply it atomically.
+fn process_sharded(
+    options: &Options,
+    rows: InputRows,
+    outputs: &mut Outputs,
+) -> Result<AppState, Box<dyn Error>> {
+    let (state, _) = initial_state(options)?;
+    let mut sharded = ShardedEngine::start(state, options.shards, || outputs.processor(options));
 
-    let mut state = AppState::new();
+    for row in rows {
+        let row = row?;
+        let invalid = match row.record {
+            Ok(record) => match record.to_tx() {
+                Ok(_) if record.record_type == "transfer" => {
+                    return Err(Box::<dyn Error>::from(format!(
+                        "line {}: transfers cannot be combined with --shards",
+                        row.line
+                    )));
+                }
+                Ok(_) => {
+                    sharded.submit_record_at(row.line, record)?;
+                    continue;
+                }
+                Err(err) => err.to_string(),
+            },
+            Err(reason) => reason,
+        };
+        outputs.handle(
+            options,
+            row.line,
+            &row.raw,
+            None,
+            EngineError::InvalidRecord(invalid),
+        )?;
+    }
 
-    for record in rdr.records() {
-        let record: Record = record?.deserialize(None)?;
-        let tx = record.to_tx()?;
-        let _result = tx.process(&mut state, &TxProcessorImpl);
+    let (state, rejections) = sharded.finish()?;
+    for rejection in rejections {
+        outputs.handle(
+            options,
+            rejection.line,
+            "",
+            Some(&rejection.record),
+            rejection.error,
+        )?;
     }
+    Ok(state)
+}
+
+fn processor(options: &Options) -> TxProcessorImpl {
+    TxProcessorImpl::with_policy(DisputePolicy {
+        allow_redispute_after_resolve: options.allow_redispute,
+        dispute_window: options.dispute_window,
+        dispute_window_secs: options.dispute_window_secs,
+    })
+    .with_out_of_order(options.out_of_order)
+    .with_currencies(options.currencies.clone())
+}
 
-    let mut writer = csv::WriterBuilder::new()
-        .has_headers(true)
-        .delimiter(b',')
-        .from_writer(io::stdout());
+/// Returns the state to start from and the input line after which to resume.
+///
+/// A fresh run starts from `--snapshot-in` or an empty state. A recovered run
+/// starts from the last checkpoint (or `--snapshot-in` if no checkpoint was
+/// taken yet) and replays the journal entries recorded after it.
+fn initial_state(options: &Options) -> Result<(AppState, u64), Box<dyn Error>> {
+    let (state, position) = match &options.checkpoint {
+        Some(path) if options.recover && path.exists() => {
+            let snapshot = Snapshot::load(path)?;
+            let position = snapshot.position;
+            (snapshot.into_state()?, position)
+        }
+        _ => match &options.snapshot_in {
+            None => (AppState::new(), 0),
+            Some(path) => (Snapshot::load(path)?.into_state()?, 0),
+        },
+    };
 
-    for v in state.accounts.values() {
-        writer.serialize(v.writable_record())?;
+    match (&options.journal, options.recover) {
+        (Some(journal), true) if journal.exists() => {
+            let mut engine = Engine::with_state(state).with_processor(processor(options));
+            let resume_after = Journal::replay(journal, &mut engine, position)?;
+            Ok((engine.into_state(), resume_after))
+        }
+        _ => Ok((state, position)),
     }
+}
 
-    writer.flush()?;
+fn write_rejection_summary<W: Write>(sink: &RejectionSink<W>) -> Result<(), Box<dyn Error>> {
+    let mut stderr = io::stderr().lock();
+    writeln!(stderr, "rejected transactions: {}", sink.total())?;
+    for (cause, count) in sink.summary() {
+        writeln!(stderr, "  {:?}: {}", cause, count)?;
+    }
     Ok(())
 }
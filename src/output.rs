@@ -0,0 +1,244 @@
+use crate::account::ClientAccount;
+use crate::currency::CurrencyConfig;
+use std::cmp::Ordering;
+use std::error::Error;
+use std::io::Write;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum OutputFormat {
+    Csv,
+    Json,
+    JsonLines,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "jsonl" | "ndjson" => Ok(OutputFormat::JsonLines),
+            _ => Err(Box::<dyn Error>::from(format!(
+                "Unknown output format: {}",
+                name
+            ))),
+        }
+    }
+}
+
+/// Order of the accounts in the output. Ties are broken by client id and
+/// then currency, so the output is the same from run to run.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AccountOrder {
+    /// By client id.
+    Client,
+    /// By currency, then highest total first: totals in different currencies
+    /// are not comparable.
+    Total,
+    /// Locked accounts first.
+    Locked,
+}
+
+impl AccountOrder {
+    pub fn parse(name: &str) -> Result<Self, Box<dyn Error>> {
+        match name {
+            "client" => Ok(AccountOrder::Client),
+            "total" => Ok(AccountOrder::Total),
+            "locked" => Ok(AccountOrder::Locked),
+            _ => Err(Box::<dyn Error>::from(format!(
+                "Unknown sort order: {}",
+                name
+            ))),
+        }
+    }
+}
+
+pub fn sort_accounts(accounts: &mut [&ClientAccount], order: AccountOrder) {
+    accounts.sort_by(|a, b| {
+        let by_order = match order {
+            AccountOrder::Client => Ordering::Equal,
+            AccountOrder::Total => a.currency.cmp(&b.currency).then(b.total.cmp(&a.total)),
+            AccountOrder::Locked => b.locked.cmp(&a.locked),
+        };
+        by_order
+            .then(a.client.cmp(&b.client))
+            .then_with(|| a.currency.cmp(&b.currency))
+    });
+}
+
+pub fn write_accounts<W: Write>(
+    writer: W,
+    accounts: &[&ClientAccount],
+    currencies: &CurrencyConfig,
+    format: OutputFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        OutputFormat::Csv => write_accounts_csv(writer, accounts, currencies),
+        OutputFormat::Json => write_accounts_json(writer, accounts, currencies),
+        OutputFormat::JsonLines => write_accounts_json_lines(writer, accounts, currencies),
+    }
+}
+
+/// Writes `accounts` as CSV. Input without a currency column keeps the
+/// original output format; otherwise every row names its currency and its
+/// amounts have the decimal places configured for that currency.
+pub fn write_accounts_csv<W: Write>(
+    writer: W,
+    accounts: &[&ClientAccount],
+    currencies: &CurrencyConfig,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(true)
+        .delimiter(b',')
+        .from_writer(writer);
+
+    if accounts.iter().any(|account| account.currency.is_some()) {
+        for account in accounts {
+            let decimals = currencies.decimals(account.currency.as_deref());
+            writer.serialize(account.writable_currency_record(decimals))?;
+        }
+    } else {
+        for account in accounts {
+            writer.serialize(account.writable_record())?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes `accounts` as a JSON array with one object per account.
+pub fn write_accounts_json<W: Write>(
+    mut writer: W,
+    accounts: &[&ClientAccount],
+    currencies: &CurrencyConfig,
+) -> Result<(), Box<dyn Error>> {
+    writer.write_all(b"[")?;
+    for (i, account) in accounts.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write_account_json(&mut writer, account, currencies)?;
+    }
+    writer.write_all(b"]\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes one account as a JSON object, with its amounts as numbers at the
+/// precision of its currency and no `currency` field for the unnamed one.
+pub fn write_account_json<W: Write>(
+    writer: W,
+    account: &ClientAccount,
+    currencies: &CurrencyConfig,
+) -> serde_json::Result<()> {
+    let decimals = currencies.decimals(account.currency.as_deref());
+    serde_json::to_writer(writer, &account.writable_json_record(decimals))
+}
+
+/// Writes `accounts` as JSON Lines, one object per account.
+pub fn write_accounts_json_lines<W: Write>(
+    mut writer: W,
+    accounts: &[&ClientAccount],
+    currencies: &CurrencyConfig,
+) -> Result<(), Box<dyn Error>> {
+    for account in accounts {
+        write_account_json(&mut writer, account, currencies)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sort_accounts;
+    use super::write_accounts;
+    use super::AccountOrder;
+    use super::OutputFormat;
+    use crate::account::ClientAccount;
+    use crate::currency::CurrencyConfig;
+
+    fn account(client: u16, currency: Option<&str>, available: &str) -> ClientAccount {
+        ClientAccount {
+            client,
+            currency: currency.map(str::to_string),
+            available: available.parse().unwrap(),
+            held: "0".parse().unwrap(),
+            total: available.parse().unwrap(),
+            locked: false,
+        }
+    }
+
+    fn written(accounts: &[ClientAccount], format: OutputFormat) -> String {
+        let mut currencies = CurrencyConfig::new();
+        currencies.set_decimals("JPY", 0).unwrap();
+        let mut out = Vec::new();
+        write_accounts(
+            &mut out,
+            &accounts.iter().collect::<Vec<_>>(),
+            &currencies,
+            format,
+        )
+        .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn json_amounts_are_numbers_at_the_currency_precision() {
+        let accounts = [account(1, None, "1.5"), account(2, Some("JPY"), "1200")];
+        assert_eq!(
+            written(&accounts, OutputFormat::Json),
+            "[{\"client\":1,\"available\":1.5000,\"held\":0.0000,\"total\":1.5000,\"locked\":false},\
+             {\"client\":2,\"currency\":\"JPY\",\"available\":1200,\"held\":0,\"total\":1200,\"locked\":false}]\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_have_one_account_per_line() {
+        let accounts = [account(1, None, "1.5"), account(2, None, "-0.25")];
+        let output = written(&accounts, OutputFormat::JsonLines);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "{\"client\":2,\"available\":-0.2500,\"held\":0.0000,\"total\":-0.2500,\"locked\":false}"
+        );
+        assert_eq!(written(&[], OutputFormat::Json), "[]\n");
+    }
+
+    #[test]
+    fn accounts_are_sorted_deterministically() {
+        let accounts = [
+            account(3, None, "1.0"),
+            account(1, Some("USD"), "5.0"),
+            account(2, None, "5.0"),
+            account(1, Some("EUR"), "2.0"),
+        ];
+        let mut locked = accounts.clone();
+        locked[2].locked = true;
+
+        let order = |accounts: &[ClientAccount], order| {
+            let mut sorted: Vec<_> = accounts.iter().collect();
+            sort_accounts(&mut sorted, order);
+            sorted
+                .iter()
+                .map(|account| (account.client, account.currency.clone()))
+                .collect::<Vec<_>>()
+        };
+        let eur = || Some("EUR".to_string());
+        let usd = || Some("USD".to_string());
+
+        assert_eq!(
+            order(&accounts, AccountOrder::Client),
+            vec![(1, eur()), (1, usd()), (2, None), (3, None)]
+        );
+        assert_eq!(
+            order(&accounts, AccountOrder::Total),
+            vec![(2, None), (3, None), (1, eur()), (1, usd())]
+        );
+        assert_eq!(
+            order(&locked, AccountOrder::Locked),
+            vec![(2, None), (1, eur()), (1, usd()), (3, None)]
+        );
+    }
+}
@@ -1,248 +1,694 @@
 use crate::account::ClientAccount;
+use crate::amount::Amount;
 use crate::chargeback::Chargeback;
+use crate::currency::CurrencyConfig;
 use crate::deposit::Deposit;
 use crate::dispute::Dispute;
 use crate::errors::ErrCause;
 use crate::errors::TxProcessingError;
+use crate::general_ledger::BalanceChange;
+use crate::general_ledger::GeneralLedger;
+use crate::journal::Journal;
+use crate::ledger::Ledger;
+use crate::ledger::LedgerEntry;
+use crate::record::Record;
 use crate::resolve::Resolve;
-use crate::state::AppState;
+use crate::state::DisputableKind;
+use crate::state::DisputableTx;
+use crate::state::DisputePolicy;
 use crate::state::Flag;
+use crate::state::FlagEvent;
 use crate::state::FlaggedDeposit;
+use crate::state::FlaggedWithdrawal;
+use crate::state::OutOfOrderPolicy;
+use crate::storage::Storage;
+use crate::storage::TxChange;
+use crate::storage::Update;
+use crate::transfer::Transfer;
 use crate::withdrawal::Withdrawal;
 
 pub trait TxProcessor {
     fn process_deposit(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         deposit: &Deposit,
     ) -> Result<(), TxProcessingError>;
     fn process_withdrawal(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         withdrawal: &Withdrawal,
     ) -> Result<(), TxProcessingError>;
     fn process_dispute(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         dispute: &Dispute,
     ) -> Result<(), TxProcessingError>;
     fn process_resolve(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         resolve: &Resolve,
     ) -> Result<(), TxProcessingError>;
     fn process_chargeback(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         chargeback: &Chargeback,
     ) -> Result<(), TxProcessingError>;
+    fn process_transfer(
+        &self,
+        storage: &mut dyn Storage,
+        transfer: &Transfer,
+    ) -> Result<(), TxProcessingError>;
 }
 
-pub struct TxProcessorImpl;
+#[derive(Debug)]
+pub struct TxProcessorImpl {
+    policy: DisputePolicy,
+    out_of_order: OutOfOrderPolicy,
+    currencies: CurrencyConfig,
+    journal: Option<Journal>,
+    ledger: Option<Ledger>,
+    general_ledger: Option<GeneralLedger>,
+}
 
 impl TxProcessorImpl {
     pub fn new() -> Self {
-        Self {}
+        Self::with_policy(DisputePolicy::default())
     }
-}
 
-impl Default for TxProcessorImpl {
-    fn default() -> Self {
-        Self::new()
+    pub fn with_policy(policy: DisputePolicy) -> Self {
+        Self {
+            policy,
+            out_of_order: OutOfOrderPolicy::default(),
+            currencies: CurrencyConfig::new(),
+            journal: None,
+            ledger: None,
+            general_ledger: None,
+        }
     }
-}
 
-impl TxProcessor for TxProcessorImpl {
-    fn process_deposit(
-        &self,
-        state: &mut AppState,
-        deposit: &Deposit,
-    ) -> Result<(), TxProcessingError> {
-        if deposit.amount <= 0.0 {
-            return Err(TxProcessingError::Deposit(
-                ErrCause::AmountLessThanOrEqualToZero,
-            ));
-        }
+    pub fn with_out_of_order(mut self, out_of_order: OutOfOrderPolicy) -> Self {
+        self.out_of_order = out_of_order;
+        self
+    }
 
-        let deposit_result = match state.get_account_as_mut(deposit.client) {
-            Some(client_acccount) if client_acccount.locked => {
-                Err(TxProcessingError::Deposit(ErrCause::ClientAccountLocked))
-            }
-            Some(client_account) => {
-                client_account.deposit(deposit.amount);
-                Ok(())
-            }
+    pub fn with_currencies(mut self, currencies: CurrencyConfig) -> Self {
+        self.currencies = currencies;
+        self
+    }
+
+    pub fn with_journal(mut self, journal: Journal) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    pub fn with_ledger(mut self, ledger: Ledger) -> Self {
+        self.ledger = Some(ledger);
+        self
+    }
+
+    pub fn with_general_ledger(mut self, general_ledger: GeneralLedger) -> Self {
+        self.general_ledger = Some(general_ledger);
+        self
+    }
+
+    /// Applies `update` to `storage`, appending the transaction to the
+    /// journal first if one is attached. Called once the transaction has
+    /// passed validation. Should the storage fail, the entry is voided so that
+    /// recovery does not replay a transaction that never took effect.
+    ///
+    /// Returns how the update changes the balances of its accounts, which
+    /// the general ledger posting is derived from. They are only worked out
+    /// when a general ledger is attached.
+    fn commit(
+        &self,
+        storage: &mut dyn Storage,
+        update: &Update,
+        record: impl FnOnce() -> Record,
+    ) -> Result<Vec<BalanceChange>, ErrCause> {
+        let changes = match &self.general_ledger {
+            None => Vec::new(),
+            Some(_) => std::iter::once(&update.account)
+                .chain(&update.counterpart)
+                .map(|after| {
+                    let before = storage.account(&after.key())?;
+                    Ok(BalanceChange::between(before.as_ref(), after))
+                })
+                .collect::<Result<_, ErrCause>>()?,
+        };
+        let journal = match &self.journal {
             None => {
-                state.accounts.insert(
-                    deposit.client,
-                    ClientAccount {
-                        client: deposit.client,
-                        available: deposit.amount,
-                        held: 0.0,
-                        total: deposit.amount,
-                        locked: false,
-                    },
-                );
-                Ok(())
+                storage.apply(update)?;
+                return Ok(changes);
             }
+            Some(journal) => journal,
         };
+        let record = record();
+        journal
+            .append(&record)
+            .map_err(|_| ErrCause::JournalWriteFailed)?;
+        if let Err(err) = storage.apply(update) {
+            journal
+                .void(&record)
+                .map_err(|_| ErrCause::JournalWriteFailed)?;
+            return Err(err.into());
+        }
+        Ok(changes)
+    }
+}
 
-        match deposit_result {
-            ok @ Ok(()) => {
-                state.deposits.insert(
-                    deposit.tx,
-                    FlaggedDeposit {
-                        deposit: deposit.clone(),
-                        flag: Flag::NotDisputed,
-                    },
-                );
-                ok
-            }
-            err => err,
+impl TxProcessorImpl {
+    /// Writes the applied `update` and the resulting balances of its accounts
+    /// to the ledger and posts it to the general ledger, if they are attached.
+    /// `changes` are as returned by [`Self::commit`], and `disputed` is the
+    /// kind of the transaction a dispute, resolve or chargeback refers to.
+    fn post(
+        &self,
+        update: &Update,
+        changes: &[BalanceChange],
+        record_type: &'static str,
+        disputed: Option<DisputableKind>,
+        amount: Amount,
+        timestamp: Option<u64>,
+    ) {
+        let tx = update.tx_id();
+        for account in std::iter::once(&update.account).chain(&update.counterpart) {
+            self.write_ledger(account, record_type, tx, amount, timestamp);
+        }
+        if let Some(general_ledger) = &self.general_ledger {
+            general_ledger.post(&general_ledger.posting(
+                record_type,
+                disputed,
+                amount,
+                update,
+                changes,
+            ));
         }
     }
 
-    fn process_withdrawal(
+    /// Writes the applied transaction and the resulting balances of `account`
+    /// to the ledger, if one is attached.
+    fn write_ledger(
         &self,
-        state: &mut AppState,
-        withdrawal: &Withdrawal,
-    ) -> Result<(), TxProcessingError> {
-        if withdrawal.amount <= 0.0 {
-            return Err(TxProcessingError::Withdrawal(
-                ErrCause::AmountLessThanOrEqualToZero,
+        account: &ClientAccount,
+        record_type: &'static str,
+        tx: u32,
+        amount: Amount,
+        timestamp: Option<u64>,
+    ) {
+        if let Some(ledger) = &self.ledger {
+            ledger.record(&LedgerEntry::new(
+                record_type,
+                tx,
+                amount,
+                timestamp,
+                account,
             ));
         }
+    }
 
-        match state.accounts.get_mut(&withdrawal.client) {
-            Some(client_account) if client_account.locked => {
-                Err(TxProcessingError::Withdrawal(ErrCause::ClientAccountLocked))
-            }
-            Some(client_account) if client_account.available < withdrawal.amount => {
-                Err(TxProcessingError::Withdrawal(ErrCause::InsufficientFunds))
-            }
-            Some(client_account) => {
-                client_account.withdraw(withdrawal.amount);
-                Ok(())
-            }
-            None => Err(TxProcessingError::Withdrawal(
-                ErrCause::ClientAccountNotFound,
-            )),
+    /// Number of the next deposit, withdrawal or transfer. Transactions are only
+    /// numbered when there is a dispute window to check.
+    fn next_seq(&self, storage: &dyn Storage) -> Result<u64, ErrCause> {
+        match self.policy.dispute_window {
+            None => Ok(0),
+            Some(_) => Ok(storage.accepted()?),
         }
     }
 
-    fn process_dispute(
+    /// Checks `timestamp` against the latest one accepted for `client` and
+    /// returns the client's new latest timestamp, if the transaction moves it.
+    fn clock(
         &self,
-        state: &mut AppState,
-        dispute: &Dispute,
-    ) -> Result<(), TxProcessingError> {
-        let curr_tx = dispute.tx;
-        let flagged_deposit = match state.deposits.get(&curr_tx).cloned() {
-            None => return Err(TxProcessingError::Dispute(ErrCause::ClientTxNotFound)),
-            Some(deposit) => deposit,
+        storage: &dyn Storage,
+        client: u16,
+        timestamp: Option<u64>,
+    ) -> Result<Option<u64>, ErrCause> {
+        let timestamp = match timestamp {
+            None => return Ok(None),
+            Some(timestamp) => timestamp,
         };
+        match storage.last_timestamp(client)? {
+            Some(last) if timestamp < last => match self.out_of_order {
+                OutOfOrderPolicy::Reject => Err(ErrCause::TimestampOutOfOrder),
+                OutOfOrderPolicy::Accept => Ok(None),
+            },
+            Some(last) if timestamp == last => Ok(None),
+            _ => Ok(Some(timestamp)),
+        }
+    }
+
+    /// Looks up the transaction a dispute, resolve or chargeback stamped
+    /// `timestamp` refers to. One that is out of its dispute window and not
+    /// under dispute fails with `expired`, whether the storage has pruned it
+    /// yet or not.
+    fn find_disputed(
+        &self,
+        storage: &dyn Storage,
+        tx: u32,
+        timestamp: Option<u64>,
+        expired: ErrCause,
+    ) -> Result<DisputableTx, ErrCause> {
+        let policy = &self.policy;
+        if policy.dispute_window.is_none() && policy.dispute_window_secs.is_none() {
+            return storage.disputable(tx)?.ok_or(ErrCause::ClientTxNotFound);
+        }
+        match storage.disputable(tx)? {
+            Some(disputed) if disputed.flag == Flag::Disputed => Ok(disputed),
+            Some(disputed) => {
+                let is_expired = policy.is_expired_at(disputed.timestamp, timestamp)
+                    || policy.is_expired(disputed.seq, storage.accepted()?);
+                if is_expired {
+                    Err(expired)
+                } else {
+                    Ok(disputed)
+                }
+            }
+            None if policy.dispute_window.is_some()
+                && storage.is_known_tx(tx)?
+                && !storage.is_transfer(tx)? =>
+            {
+                Err(expired)
+            }
+            None => Err(ErrCause::ClientTxNotFound),
+        }
+    }
+
+    fn deposit(&self, storage: &mut dyn Storage, deposit: &Deposit) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, deposit.client, deposit.timestamp)?;
 
-        if flagged_deposit.deposit.client != dispute.client {
-            return Err(TxProcessingError::Dispute(ErrCause::ClientDidNotMatch));
+        if storage.is_known_tx(deposit.tx)? {
+            return Err(ErrCause::DuplicateTransactionId);
         }
 
-        if flagged_deposit.is_disputed() {
-            return Err(TxProcessingError::Dispute(
-                ErrCause::ClientTxAlreadyInDispute,
-            ));
+        if !deposit.amount.is_positive() {
+            return Err(ErrCause::AmountLessThanOrEqualToZero);
+        }
+
+        if !self
+            .currencies
+            .accepts(deposit.currency.as_deref(), deposit.amount)
+        {
+            return Err(ErrCause::AmountPrecisionExceeded);
         }
 
-        match state.get_account_as_mut(dispute.client) {
+        let account = match storage.account(&(deposit.client, deposit.currency.clone()))? {
             Some(client_account) if client_account.locked => {
-                Err(TxProcessingError::Dispute(ErrCause::ClientAccountLocked))
+                return Err(ErrCause::ClientAccountLocked)
             }
-            Some(client_account) => {
-                client_account.held += flagged_deposit.deposit.amount;
-                client_account.available -= flagged_deposit.deposit.amount;
-                if let Some(deposit) = state.get_tx_as_mut(curr_tx) {
-                    deposit.mark_disputed();
-                }
-                Ok(())
+            Some(mut client_account) => {
+                client_account.deposit(deposit.amount)?;
+                client_account
             }
-            None => panic!("Account should exist for client in {:?}", dispute),
-        }
+            None => ClientAccount {
+                client: deposit.client,
+                currency: deposit.currency.clone(),
+                available: deposit.amount,
+                held: Amount::ZERO,
+                total: deposit.amount,
+                locked: false,
+            },
+        };
+
+        let seq = self.next_seq(storage)?;
+        let update = Update {
+            account,
+            counterpart: None,
+            tx: TxChange::Deposit(FlaggedDeposit {
+                deposit: deposit.clone(),
+                flag: Flag::NotDisputed,
+                seq,
+            }),
+            prune_before: self.policy.prune_before(seq),
+            timestamp,
+            counterpart_timestamp: None,
+        };
+        let changes = self.commit(storage, &update, || Record::from(deposit))?;
+        self.post(
+            &update,
+            &changes,
+            "deposit",
+            None,
+            deposit.amount,
+            deposit.timestamp,
+        );
+        Ok(())
     }
 
-    fn process_resolve(
+    fn withdrawal(
         &self,
-        state: &mut AppState,
-        resolve: &Resolve,
-    ) -> Result<(), TxProcessingError> {
-        let curr_tx = resolve.tx;
-        let flagged_deposit = match state.deposits.get(&curr_tx).cloned() {
-            None => return Err(TxProcessingError::Resolve(ErrCause::ClientTxNotFound)),
-            Some(deposit) => deposit,
-        };
+        storage: &mut dyn Storage,
+        withdrawal: &Withdrawal,
+    ) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, withdrawal.client, withdrawal.timestamp)?;
 
-        if flagged_deposit.deposit.client != resolve.client {
-            return Err(TxProcessingError::Resolve(ErrCause::ClientDidNotMatch));
+        if storage.is_known_tx(withdrawal.tx)? {
+            return Err(ErrCause::DuplicateTransactionId);
         }
 
-        if !flagged_deposit.is_disputed() {
-            return Err(TxProcessingError::Resolve(
-                ErrCause::ClientTxIsNotUnderDispute,
-            ));
+        if !withdrawal.amount.is_positive() {
+            return Err(ErrCause::AmountLessThanOrEqualToZero);
         }
 
-        let deposit = flagged_deposit.deposit;
+        if !self
+            .currencies
+            .accepts(withdrawal.currency.as_deref(), withdrawal.amount)
+        {
+            return Err(ErrCause::AmountPrecisionExceeded);
+        }
+
+        let mut account =
+            match storage.account(&(withdrawal.client, withdrawal.currency.clone()))? {
+                Some(client_account) if client_account.locked => {
+                    return Err(ErrCause::ClientAccountLocked)
+                }
+                Some(client_account) if client_account.available < withdrawal.amount => {
+                    return Err(ErrCause::InsufficientFunds)
+                }
+                Some(client_account) => client_account,
+                None => return Err(ErrCause::ClientAccountNotFound),
+            };
+        account.withdraw(withdrawal.amount)?;
+
+        let seq = self.next_seq(storage)?;
+        let update = Update {
+            account,
+            counterpart: None,
+            tx: TxChange::Withdrawal(FlaggedWithdrawal {
+                withdrawal: withdrawal.clone(),
+                flag: Flag::NotDisputed,
+                seq,
+            }),
+            prune_before: self.policy.prune_before(seq),
+            timestamp,
+            counterpart_timestamp: None,
+        };
+        let changes = self.commit(storage, &update, || Record::from(withdrawal))?;
+        self.post(
+            &update,
+            &changes,
+            "withdrawal",
+            None,
+            withdrawal.amount,
+            withdrawal.timestamp,
+        );
+        Ok(())
+    }
+
+    fn dispute(&self, storage: &mut dyn Storage, dispute: &Dispute) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, dispute.client, dispute.timestamp)?;
+
+        let disputed = self.find_disputed(
+            storage,
+            dispute.tx,
+            dispute.timestamp,
+            ErrCause::DisputeWindowExpired,
+        )?;
+
+        if disputed.client != dispute.client {
+            return Err(ErrCause::ClientDidNotMatch);
+        }
 
-        match state.get_account_as_mut(resolve.client) {
+        let next_flag = disputed.flag.transition(FlagEvent::Dispute, &self.policy)?;
+
+        let mut account = match storage.account(&(dispute.client, disputed.currency.clone()))? {
             Some(client_account) if client_account.locked => {
-                Err(TxProcessingError::Resolve(ErrCause::ClientAccountLocked))
+                return Err(ErrCause::ClientAccountLocked)
             }
-            Some(client_account) => {
-                client_account.held -= deposit.amount;
-                client_account.available += deposit.amount;
-                if let Some(deposit) = state.get_tx_as_mut(curr_tx) {
-                    deposit.mark_resolved();
-                }
-                Ok(())
+            Some(client_account) => client_account,
+            None => panic!("Account should exist for client in {:?}", dispute),
+        };
+        match disputed.kind {
+            // Disputed credit: the deposited funds are held back.
+            DisputableKind::Deposit => account.hold(disputed.amount),
+            // Disputed debit: the withdrawn funds are claimed back and held.
+            DisputableKind::Withdrawal => account.add_held(disputed.amount),
+        }?;
+
+        let update = Update {
+            account,
+            counterpart: None,
+            tx: TxChange::Flag(dispute.tx, next_flag),
+            prune_before: None,
+            timestamp,
+            counterpart_timestamp: None,
+        };
+        let changes = self.commit(storage, &update, || Record::from(dispute))?;
+        self.post(
+            &update,
+            &changes,
+            "dispute",
+            Some(disputed.kind),
+            disputed.amount,
+            dispute.timestamp,
+        );
+        Ok(())
+    }
+
+    fn resolve(&self, storage: &mut dyn Storage, resolve: &Resolve) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, resolve.client, resolve.timestamp)?;
+
+        let disputed = self.find_disputed(
+            storage,
+            resolve.tx,
+            resolve.timestamp,
+            ErrCause::ClientTxIsNotUnderDispute,
+        )?;
+
+        if disputed.client != resolve.client {
+            return Err(ErrCause::ClientDidNotMatch);
+        }
+
+        let next_flag = disputed.flag.transition(FlagEvent::Resolve, &self.policy)?;
+
+        let mut account = match storage.account(&(resolve.client, disputed.currency.clone()))? {
+            Some(client_account) if client_account.locked => {
+                return Err(ErrCause::ClientAccountLocked)
             }
+            Some(client_account) => client_account,
             None => panic!("Account should exist for client in {:?}", resolve),
-        }
+        };
+        match disputed.kind {
+            // The deposit stands: held funds become available again.
+            DisputableKind::Deposit => account.release(disputed.amount),
+            // The withdrawal stands: the claimed back funds are dropped.
+            DisputableKind::Withdrawal => account.drop_held(disputed.amount),
+        }?;
+
+        let update = Update {
+            account,
+            counterpart: None,
+            tx: TxChange::Flag(resolve.tx, next_flag),
+            prune_before: None,
+            timestamp,
+            counterpart_timestamp: None,
+        };
+        let changes = self.commit(storage, &update, || Record::from(resolve))?;
+        self.post(
+            &update,
+            &changes,
+            "resolve",
+            Some(disputed.kind),
+            disputed.amount,
+            resolve.timestamp,
+        );
+        Ok(())
     }
 
-    fn process_chargeback(
+    fn chargeback(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         chargeback: &Chargeback,
-    ) -> Result<(), TxProcessingError> {
-        let curr_tx = chargeback.tx;
-        let flagged_deposit = match state.get_tx(curr_tx).cloned() {
-            None => return Err(TxProcessingError::Chargeback(ErrCause::ClientTxNotFound)),
-            Some(deposit) => deposit,
+    ) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, chargeback.client, chargeback.timestamp)?;
+
+        let disputed = self.find_disputed(
+            storage,
+            chargeback.tx,
+            chargeback.timestamp,
+            ErrCause::ClientTxIsNotUnderDispute,
+        )?;
+
+        if disputed.client != chargeback.client {
+            return Err(ErrCause::ClientDidNotMatch);
+        }
+
+        let next_flag = disputed
+            .flag
+            .transition(FlagEvent::Chargeback, &self.policy)?;
+
+        let mut account = match storage.account(&(chargeback.client, disputed.currency.clone()))? {
+            Some(client_account) if client_account.locked => {
+                return Err(ErrCause::ClientAccountLocked)
+            }
+            Some(client_account) => client_account,
+            None => panic!("Account should exist for client in {:?}", chargeback),
         };
+        match disputed.kind {
+            // The deposit is reversed: held funds leave the account.
+            DisputableKind::Deposit => account.drop_held(disputed.amount),
+            // The withdrawal is reversed: held funds are returned to the client.
+            DisputableKind::Withdrawal => account.release(disputed.amount),
+        }?;
+        account.locked = true;
 
-        if flagged_deposit.deposit.client != chargeback.client {
-            return Err(TxProcessingError::Chargeback(ErrCause::ClientDidNotMatch));
+        let update = Update {
+            account,
+            counterpart: None,
+            tx: TxChange::Flag(chargeback.tx, next_flag),
+            prune_before: None,
+            timestamp,
+            counterpart_timestamp: None,
+        };
+        let changes = self.commit(storage, &update, || Record::from(chargeback))?;
+        self.post(
+            &update,
+            &changes,
+            "chargeback",
+            Some(disputed.kind),
+            disputed.amount,
+            chargeback.timestamp,
+        );
+        Ok(())
+    }
+
+    fn transfer(&self, storage: &mut dyn Storage, transfer: &Transfer) -> Result<(), ErrCause> {
+        let timestamp = self.clock(storage, transfer.client, transfer.timestamp)?;
+        let counterpart_timestamp =
+            self.clock(storage, transfer.destination, transfer.timestamp)?;
+
+        if storage.is_known_tx(transfer.tx)? {
+            return Err(ErrCause::DuplicateTransactionId);
         }
 
-        if !flagged_deposit.is_disputed() {
-            return Err(TxProcessingError::Chargeback(
-                ErrCause::ClientTxIsNotUnderDispute,
-            ));
+        if !transfer.amount.is_positive() {
+            return Err(ErrCause::AmountLessThanOrEqualToZero);
         }
 
-        let deposit = flagged_deposit.deposit;
+        if !self
+            .currencies
+            .accepts(transfer.currency.as_deref(), transfer.amount)
+        {
+            return Err(ErrCause::AmountPrecisionExceeded);
+        }
+
+        if transfer.destination == transfer.client {
+            return Err(ErrCause::TransferToSelf);
+        }
 
-        match state.get_account_as_mut(chargeback.client) {
+        let mut source = match storage.account(&(transfer.client, transfer.currency.clone()))? {
             Some(client_account) if client_account.locked => {
-                Err(TxProcessingError::Chargeback(ErrCause::ClientAccountLocked))
+                return Err(ErrCause::ClientAccountLocked)
             }
-            Some(ref mut client_account) => {
-                client_account.held -= deposit.amount;
-                client_account.total -= deposit.amount;
-                client_account.locked = true;
-                if let Some(deposit) = state.get_tx_as_mut(curr_tx) {
-                    deposit.mark_chargedback();
-                }
-                Ok(())
+            Some(client_account) if client_account.available < transfer.amount => {
+                return Err(ErrCause::InsufficientFunds)
             }
-            None => panic!("Account should exist for client in {:?}", chargeback),
-        }
+            Some(client_account) => client_account,
+            None => return Err(ErrCause::ClientAccountNotFound),
+        };
+        let destination =
+            match storage.account(&(transfer.destination, transfer.currency.clone()))? {
+                Some(client_account) if client_account.locked => {
+                    return Err(ErrCause::DestinationAccountLocked)
+                }
+                Some(mut client_account) => {
+                    client_account.deposit(transfer.amount)?;
+                    client_account
+                }
+                None => ClientAccount {
+                    client: transfer.destination,
+                    currency: transfer.currency.clone(),
+                    available: transfer.amount,
+                    held: Amount::ZERO,
+                    total: transfer.amount,
+                    locked: false,
+                },
+            };
+        source.withdraw(transfer.amount)?;
+
+        let seq = self.next_seq(storage)?;
+        let update = Update {
+            account: source,
+            counterpart: Some(destination),
+            tx: TxChange::Transfer(transfer.tx),
+            prune_before: self.policy.prune_before(seq),
+            timestamp,
+            counterpart_timestamp,
+        };
+        let changes = self.commit(storage, &update, || Record::from(transfer))?;
+        self.post(
+            &update,
+            &changes,
+            "transfer",
+            None,
+            transfer.amount,
+            transfer.timestamp,
+        );
+        Ok(())
+    }
+}
+
+impl Default for TxProcessorImpl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TxProcessor for TxProcessorImpl {
+    fn process_deposit(
+        &self,
+        storage: &mut dyn Storage,
+        deposit: &Deposit,
+    ) -> Result<(), TxProcessingError> {
+        self.deposit(storage, deposit)
+            .map_err(TxProcessingError::Deposit)
+    }
+
+    fn process_withdrawal(
+        &self,
+        storage: &mut dyn Storage,
+        withdrawal: &Withdrawal,
+    ) -> Result<(), TxProcessingError> {
+        self.withdrawal(storage, withdrawal)
+            .map_err(TxProcessingError::Withdrawal)
+    }
+
+    fn process_dispute(
+        &self,
+        storage: &mut dyn Storage,
+        dispute: &Dispute,
+    ) -> Result<(), TxProcessingError> {
+        self.dispute(storage, dispute)
+            .map_err(TxProcessingError::Dispute)
+    }
+
+    fn process_resolve(
+        &self,
+        storage: &mut dyn Storage,
+        resolve: &Resolve,
+    ) -> Result<(), TxProcessingError> {
+        self.resolve(storage, resolve)
+            .map_err(TxProcessingError::Resolve)
+    }
+
+    fn process_chargeback(
+        &self,
+        storage: &mut dyn Storage,
+        chargeback: &Chargeback,
+    ) -> Result<(), TxProcessingError> {
+        self.chargeback(storage, chargeback)
+            .map_err(TxProcessingError::Chargeback)
+    }
+
+    fn process_transfer(
+        &self,
+        storage: &mut dyn Storage,
+        transfer: &Transfer,
+    ) -> Result<(), TxProcessingError> {
+        self.transfer(storage, transfer)
+            .map_err(TxProcessingError::Transfer)
     }
 }
@@ -0,0 +1,73 @@
+use serde::Serialize;
+use std::error::Error;
+use std::io::Write;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct QuarantinedRow<'a> {
+    pub line: u64,
+    pub error: &'a str,
+    pub row: &'a str,
+}
+
+/// Collects malformed input rows in lenient mode, so they can be fixed and
+/// replayed without aborting the rest of the file.
+pub struct QuarantineSink<W: Write> {
+    writer: csv::Writer<W>,
+    count: u64,
+}
+
+impl<W: Write> QuarantineSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: csv::WriterBuilder::new()
+                .has_headers(true)
+                .delimiter(b',')
+                .from_writer(writer),
+            count: 0,
+        }
+    }
+
+    pub fn record(&mut self, row: &QuarantinedRow) -> Result<(), Box<dyn Error>> {
+        self.writer.serialize(row)?;
+        self.count += 1;
+        Ok(())
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W, Box<dyn Error>> {
+        self.writer
+            .into_inner()
+            .map_err(|err| err.to_string().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QuarantineSink;
+    use super::QuarantinedRow;
+
+    #[test]
+    fn quarantined_rows_keep_line_error_and_raw_row() {
+        let mut sink = QuarantineSink::new(Vec::new());
+        sink.record(&QuarantinedRow {
+            line: 4,
+            error: "Invalid csv row with type: refund",
+            row: "refund, 1, 4, 1.0",
+        })
+        .unwrap();
+
+        assert_eq!(sink.count(), 1);
+        assert_eq!(
+            String::from_utf8(sink.into_inner().unwrap()).unwrap(),
+            "line,error,row\n4,Invalid csv row with type: refund,\"refund, 1, 4, 1.0\"\n"
+        );
+    }
+}
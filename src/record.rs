@@ -1,22 +1,61 @@
+use crate::amount::Amount;
 use crate::chargeback::Chargeback;
 use crate::deposit::Deposit;
 use crate::dispute::Dispute;
 use crate::resolve::Resolve;
+use crate::transfer::Transfer;
 use crate::tx::Tx;
 use crate::withdrawal::Withdrawal;
 use serde::Deserialize;
+use serde::Serialize;
 use std::error::Error;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Record {
     #[serde(rename = "type")]
-    record_type: String,
-    client: u16,
-    tx: u32,
-    amount: Option<f64>,
+    pub record_type: String,
+    pub client: u16,
+    pub tx: u32,
+    pub amount: Option<Amount>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub currency: Option<String>,
+    /// Client a transfer moves funds to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<u16>,
+    /// Seconds since the Unix epoch, if the input has a timestamp column.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 impl Record {
+    /// A record without currency, destination or timestamp.
+    pub fn new(record_type: &str, client: u16, tx: u32, amount: Option<Amount>) -> Self {
+        Self {
+            record_type: record_type.to_string(),
+            client,
+            tx,
+            amount,
+            currency: None,
+            destination: None,
+            timestamp: None,
+        }
+    }
+
+    pub fn with_currency(mut self, currency: &str) -> Self {
+        self.currency = Some(currency.to_string());
+        self
+    }
+
+    pub fn with_destination(mut self, destination: u16) -> Self {
+        self.destination = Some(destination);
+        self
+    }
+
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
     pub fn to_tx(&self) -> Result<Box<dyn Tx>, Box<dyn Error>> {
         match (self.record_type.as_str(), self.amount) {
             ("deposit", Some(amount)) => {
@@ -24,6 +63,8 @@ impl Record {
                     client: self.client,
                     tx: self.tx,
                     amount,
+                    currency: self.currency.clone(),
+                    timestamp: self.timestamp,
                 });
                 Ok(desposit)
             }
@@ -32,6 +73,8 @@ impl Record {
                     client: self.client,
                     tx: self.tx,
                     amount,
+                    currency: self.currency.clone(),
+                    timestamp: self.timestamp,
                 });
                 Ok(withdraw)
             }
@@ -39,6 +82,7 @@ impl Record {
                 let dispute: Box<dyn Tx> = Box::new(Dispute {
                     client: self.client,
                     tx: self.tx,
+                    timestamp: self.timestamp,
                 });
                 Ok(dispute)
             }
@@ -46,6 +90,7 @@ impl Record {
                 let resolve: Box<dyn Tx> = Box::new(Resolve {
                     client: self.client,
                     tx: self.tx,
+                    timestamp: self.timestamp,
                 });
                 Ok(resolve)
             }
@@ -53,9 +98,24 @@ impl Record {
                 let chargeback: Box<dyn Tx> = Box::new(Chargeback {
                     client: self.client,
                     tx: self.tx,
+                    timestamp: self.timestamp,
                 });
                 Ok(chargeback)
             }
+            ("transfer", Some(amount)) => {
+                let destination = self.destination.ok_or_else(|| {
+                    Box::<dyn Error>::from("Invalid csv row with type: transfer, no destination")
+                })?;
+                let transfer: Box<dyn Tx> = Box::new(Transfer {
+                    client: self.client,
+                    destination,
+                    tx: self.tx,
+                    amount,
+                    currency: self.currency.clone(),
+                    timestamp: self.timestamp,
+                });
+                Ok(transfer)
+            }
             (record_type, _) => Err(Box::<dyn Error>::from(format!(
                 "Invalid csv row with type: {}",
                 record_type
@@ -63,3 +123,87 @@ impl Record {
         }
     }
 }
+
+impl From<&Deposit> for Record {
+    fn from(deposit: &Deposit) -> Self {
+        Record {
+            record_type: "deposit".to_string(),
+            client: deposit.client,
+            tx: deposit.tx,
+            amount: Some(deposit.amount),
+            currency: deposit.currency.clone(),
+            destination: None,
+            timestamp: deposit.timestamp,
+        }
+    }
+}
+
+impl From<&Withdrawal> for Record {
+    fn from(withdrawal: &Withdrawal) -> Self {
+        Record {
+            record_type: "withdrawal".to_string(),
+            client: withdrawal.client,
+            tx: withdrawal.tx,
+            amount: Some(withdrawal.amount),
+            currency: withdrawal.currency.clone(),
+            destination: None,
+            timestamp: withdrawal.timestamp,
+        }
+    }
+}
+
+impl From<&Dispute> for Record {
+    fn from(dispute: &Dispute) -> Self {
+        Record {
+            record_type: "dispute".to_string(),
+            client: dispute.client,
+            tx: dispute.tx,
+            amount: None,
+            currency: None,
+            destination: None,
+            timestamp: dispute.timestamp,
+        }
+    }
+}
+
+impl From<&Resolve> for Record {
+    fn from(resolve: &Resolve) -> Self {
+        Record {
+            record_type: "resolve".to_string(),
+            client: resolve.client,
+            tx: resolve.tx,
+            amount: None,
+            currency: None,
+            destination: None,
+            timestamp: resolve.timestamp,
+        }
+    }
+}
+
+impl From<&Chargeback> for Record {
+    fn from(chargeback: &Chargeback) -> Self {
+        Record {
+            record_type: "chargeback".to_string(),
+            client: chargeback.client,
+            tx: chargeback.tx,
+            amount: None,
+            currency: None,
+            destination: None,
+            timestamp: chargeback.timestamp,
+        }
+    }
+}
+
+impl From<&Transfer> for Record {
+    fn from(transfer: &Transfer) -> Self {
+        Record {
+            record_type: "transfer".to_string(),
+            client: transfer.client,
+            tx: transfer.tx,
+            amount: Some(transfer.amount),
+            currency: transfer.currency.clone(),
+            destination: Some(transfer.destination),
+            timestamp: transfer.timestamp,
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::errors::ErrCause;
+use crate::errors::TxProcessingError;
+use crate::record::Record;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::io::Write;
+use std::path::Path;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Rejection {
+    pub line: u64,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub client: u16,
+    pub tx: u32,
+    pub cause: ErrCause,
+    pub timestamp: Option<u64>,
+}
+
+impl Rejection {
+    pub fn new(line: u64, record: &Record, err: &TxProcessingError) -> Self {
+        Self {
+            line,
+            record_type: record.record_type.clone(),
+            client: record.client,
+            tx: record.tx,
+            cause: err.cause(),
+            timestamp: record.timestamp,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum RejectionFormat {
+    Csv,
+    JsonLines,
+}
+
+impl RejectionFormat {
+    /// `.jsonl` and `.ndjson` files get JSON Lines, anything else gets CSV.
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") | Some("ndjson") => RejectionFormat::JsonLines,
+            _ => RejectionFormat::Csv,
+        }
+    }
+}
+
+enum RejectionWriter<W: Write> {
+    Csv(Box<csv::Writer<W>>),
+    JsonLines(W),
+}
+
+/// Records every rejected transaction and keeps per-cause counts for the
+/// end-of-run summary.
+pub struct RejectionSink<W: Write> {
+    writer: RejectionWriter<W>,
+    counts: BTreeMap<ErrCause, u64>,
+}
+
+impl<W: Write> RejectionSink<W> {
+    pub fn new(format: RejectionFormat, writer: W) -> Self {
+        let writer = match format {
+            RejectionFormat::Csv => RejectionWriter::Csv(Box::new(
+                csv::WriterBuilder::new()
+                    .has_headers(true)
+                    .delimiter(b',')
+                    .from_writer(writer),
+            )),
+            RejectionFormat::JsonLines => RejectionWriter::JsonLines(writer),
+        };
+        Self {
+            writer,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    pub fn record(&mut self, rejection: &Rejection) -> Result<(), Box<dyn Error>> {
+        match &mut self.writer {
+            RejectionWriter::Csv(writer) => writer.serialize(rejection)?,
+            RejectionWriter::JsonLines(writer) => {
+                serde_json::to_writer(&mut *writer, rejection)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        *self.counts.entry(rejection.cause).or_insert(0) += 1;
+        Ok(())
+    }
+
+    pub fn summary(&self) -> &BTreeMap<ErrCause, u64> {
+        &self.counts
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        match &mut self.writer {
+            RejectionWriter::Csv(writer) => writer.flush()?,
+            RejectionWriter::JsonLines(writer) => writer.flush()?,
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> Result<W, Box<dyn Error>> {
+        match self.writer {
+            RejectionWriter::Csv(writer) => {
+                writer.into_inner().map_err(|err| err.to_string().into())
+            }
+            RejectionWriter::JsonLines(mut writer) => {
+                writer.flush()?;
+                Ok(writer)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rejection;
+    use super::RejectionFormat;
+    use super::RejectionSink;
+    use crate::errors::ErrCause;
+    use std::path::Path;
+
+    fn rejection(line: u64, cause: ErrCause) -> Rejection {
+        Rejection {
+            line,
+            record_type: "withdrawal".to_string(),
+            client: 1,
+            tx: 7,
+            cause,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn format_is_picked_from_extension() {
+        assert_eq!(
+            RejectionFormat::from_path(Path::new("rejected.jsonl")),
+            RejectionFormat::JsonLines
+        );
+        assert_eq!(
+            RejectionFormat::from_path(Path::new("rejected.csv")),
+            RejectionFormat::Csv
+        );
+    }
+
+    #[test]
+    fn csv_sink_writes_header_and_rows() {
+        let mut sink = RejectionSink::new(RejectionFormat::Csv, Vec::new());
+        sink.record(&rejection(3, ErrCause::InsufficientFunds))
+            .unwrap();
+
+        let output = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "line,type,client,tx,cause,timestamp\n3,withdrawal,1,7,InsufficientFunds,\n"
+        );
+    }
+
+    #[test]
+    fn json_lines_sink_writes_one_object_per_line() {
+        let mut sink = RejectionSink::new(RejectionFormat::JsonLines, Vec::new());
+        sink.record(&rejection(3, ErrCause::InsufficientFunds))
+            .unwrap();
+        sink.record(&rejection(4, ErrCause::ClientAccountLocked))
+            .unwrap();
+
+        let output = String::from_utf8(sink.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            output,
+            "{\"line\":3,\"type\":\"withdrawal\",\"client\":1,\"tx\":7,\"cause\":\"InsufficientFunds\",\"timestamp\":null}\n\
+             {\"line\":4,\"type\":\"withdrawal\",\"client\":1,\"tx\":7,\"cause\":\"ClientAccountLocked\",\"timestamp\":null}\n"
+        );
+    }
+
+    #[test]
+    fn sink_counts_rejections_per_cause() {
+        let mut sink = RejectionSink::new(RejectionFormat::Csv, Vec::new());
+        sink.record(&rejection(1, ErrCause::InsufficientFunds))
+            .unwrap();
+        sink.record(&rejection(2, ErrCause::ClientTxNotFound))
+            .unwrap();
+        sink.record(&rejection(3, ErrCause::InsufficientFunds))
+            .unwrap();
+
+        assert_eq!(sink.summary().get(&ErrCause::InsufficientFunds), Some(&2));
+        assert_eq!(sink.summary().get(&ErrCause::ClientTxNotFound), Some(&1));
+        assert_eq!(sink.total(), 3);
+    }
+}
@@ -1,21 +1,22 @@
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use crate::tx::Tx;
 
 #[derive(Debug)]
 pub struct Resolve {
     pub client: u16,
     pub tx: u32,
+    pub timestamp: Option<u64>,
 }
 
 impl Tx for Resolve {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError> {
-        visitor.process_resolve(state, self)
+        visitor.process_resolve(storage, self)
     }
 }
 
@@ -30,13 +31,21 @@ mod tests {
     use crate::state::AppState;
     use crate::state::Flag;
     use crate::state::FlaggedDeposit;
+    use crate::state::FlaggedWithdrawal;
     use crate::tx::Tx;
+    use crate::withdrawal::Withdrawal;
 
     #[test]
     fn resolve_fails_on_non_existent_deposit() {
-        let resolve = Resolve { client: 1, tx: 2 };
+        let resolve = Resolve {
+            client: 1,
+            tx: 2,
+            timestamp: None,
+        };
         let mut state = AppState::new();
-        let err = resolve.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let err = resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
 
         assert_eq!(err, TxProcessingError::Resolve(ErrCause::ClientTxNotFound));
     }
@@ -48,6 +57,7 @@ mod tests {
         let resolve = Resolve {
             client: client_id,
             tx: tx_id,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.deposits.insert(
@@ -56,23 +66,29 @@ mod tests {
                 deposit: Deposit {
                     client: client_id,
                     tx: tx_id,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::NotDisputed,
+                seq: 0,
             },
         );
         state.accounts.insert(
-            client_id,
+            (client_id, None),
             ClientAccount {
                 client: client_id,
-                available: 10.0,
-                held: 0.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        let err = resolve.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let err = resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
         assert_eq!(
             err,
             TxProcessingError::Resolve(ErrCause::ClientTxIsNotUnderDispute)
@@ -81,7 +97,11 @@ mod tests {
 
     #[test]
     fn resolve_fails_if_client_in_does_not_match() {
-        let resolve = Resolve { client: 1, tx: 1 };
+        let resolve = Resolve {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -89,20 +109,29 @@ mod tests {
                 deposit: Deposit {
                     client: 2,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
-        let err = resolve.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let err = resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
         assert_eq!(err, TxProcessingError::Resolve(ErrCause::ClientDidNotMatch))
     }
 
     #[test]
     #[should_panic]
     fn resolve_panics_if_account_is_absent_and_diposit_is_present() {
-        let resolve = Resolve { client: 1, tx: 1 };
+        let resolve = Resolve {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -110,18 +139,27 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
-        resolve.process(&mut state, &TxProcessorImpl).unwrap_err();
+        resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
     }
 
     #[test]
     fn resolve_marks_the_deposit_chargebacked() {
-        let resolve = Resolve { client: 1, tx: 1 };
+        let resolve = Resolve {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
         let mut state = AppState::new();
         state.deposits.insert(
             1,
@@ -129,25 +167,126 @@ mod tests {
                 deposit: Deposit {
                     client: 1,
                     tx: 1,
-                    amount: 20.0,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
                 },
                 flag: Flag::Disputed,
+                seq: 0,
             },
         );
 
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 20.0,
-                held: 0.0,
-                total: 20.0,
+                currency: None,
+                available: "20.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "20.0".parse().unwrap(),
                 locked: false,
             },
         );
 
-        resolve.process(&mut state, &TxProcessorImpl).unwrap();
+        resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(state.deposits.get(&1).unwrap().flag, Flag::Resolved);
     }
+
+    #[test]
+    fn resolve_of_withdrawal_drops_the_held_amount() {
+        let resolve = Resolve {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.withdrawals.insert(
+            1,
+            FlaggedWithdrawal {
+                withdrawal: Withdrawal {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::Disputed,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "20.0".parse().unwrap(),
+                total: "30.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap(),
+            &ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
+                locked: false,
+            }
+        );
+        assert_eq!(state.withdrawals.get(&1).unwrap().flag, Flag::Resolved);
+    }
+
+    #[test]
+    fn resolve_fails_if_withdrawal_not_in_dispute() {
+        let resolve = Resolve {
+            client: 1,
+            tx: 1,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.withdrawals.insert(
+            1,
+            FlaggedWithdrawal {
+                withdrawal: Withdrawal {
+                    client: 1,
+                    tx: 1,
+                    amount: "20.0".parse().unwrap(),
+                    currency: None,
+                    timestamp: None,
+                },
+                flag: Flag::NotDisputed,
+                seq: 0,
+            },
+        );
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
+                locked: false,
+            },
+        );
+
+        let err = resolve
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TxProcessingError::Resolve(ErrCause::ClientTxIsNotUnderDispute)
+        )
+    }
 }
@@ -0,0 +1,216 @@
+use crate::currency::CurrencyConfig;
+use crate::errors::EngineError;
+use crate::input::CsvRecords;
+use crate::output::sort_accounts;
+use crate::output::write_accounts_csv;
+use crate::output::AccountOrder;
+use crate::rejections::Rejection;
+use crate::rejections::RejectionFormat;
+use crate::rejections::RejectionSink;
+use crate::shard::ShardHandle;
+use crate::shard::ShardRejection;
+use std::error::Error;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Cursor;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+
+/// Line a connection sends instead of a CSV header to read the accounts of
+/// every client, or of one client with `accounts <client>`.
+pub const ACCOUNTS_REQUEST: &str = "accounts";
+
+/// Accepts transaction streams over TCP and applies them to a shared
+/// [`ShardHandle`], one thread per connection.
+///
+/// A connection either streams transactions in the CSV input format, header
+/// first, and half-closes its side when done, or sends an accounts request.
+/// A transaction stream is answered with the CSV of its rejected transactions
+/// in input order, or with an `error: line <n>: <reason>` line if it contains
+/// a malformed row; reading stops at that row. An accounts request is
+/// answered with the accounts CSV as of the time of the request.
+#[derive(Clone)]
+pub struct Server {
+    handle: ShardHandle,
+    currencies: CurrencyConfig,
+}
+
+impl Server {
+    pub fn new(handle: ShardHandle) -> Self {
+        Self {
+            handle,
+            currencies: CurrencyConfig::new(),
+        }
+    }
+
+    pub fn with_currencies(mut self, currencies: CurrencyConfig) -> Self {
+        self.currencies = currencies;
+        self
+    }
+
+    /// Serves connections from `listener` until accepting fails.
+    pub fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            thread::spawn(move || {
+                let peer = stream.peer_addr();
+                if let Err(err) = server.handle_connection(stream) {
+                    eprintln!("connection {:?}: {}", peer, err);
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn handle_connection(&self, stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(stream.try_clone()?);
+        let mut reader = BufReader::new(stream);
+        let mut first_line = String::new();
+        reader.read_line(&mut first_line)?;
+
+        match first_line.trim().strip_prefix(ACCOUNTS_REQUEST) {
+            Some(client) => self.write_accounts(&mut writer, client.trim())?,
+            None => self.apply_stream(Cursor::new(first_line).chain(reader), &mut writer)?,
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    fn write_accounts<W: Write>(&self, writer: W, client: &str) -> Result<(), Box<dyn Error>> {
+        let accounts = match client {
+            "" => self.handle.accounts()?,
+            client => self.handle.client_accounts(client.parse()?)?,
+        };
+        let mut accounts: Vec<_> = accounts.iter().collect();
+        sort_accounts(&mut accounts, AccountOrder::Client);
+        write_accounts_csv(writer, &accounts, &self.currencies)
+    }
+
+    fn apply_stream<R: Read, W: Write>(
+        &self,
+        input: R,
+        mut writer: W,
+    ) -> Result<(), Box<dyn Error>> {
+        let (rejected, rejections) = mpsc::channel::<ShardRejection>();
+        for row in CsvRecords::new(input) {
+            let row = row?;
+            let invalid = match row.record {
+                Ok(record) => match record.to_tx() {
+                    Ok(_) => {
+                        self.handle.submit_record_at(row.line, record, &rejected)?;
+                        continue;
+                    }
+                    Err(err) => err.to_string(),
+                },
+                Err(reason) => reason,
+            };
+            writeln!(writer, "error: line {}: {}", row.line, invalid)?;
+            return Ok(());
+        }
+
+        // Every shard drops its clone of the sender once it has applied the
+        // records of this stream, which ends the iteration.
+        drop(rejected);
+        let mut rejections: Vec<ShardRejection> = rejections.into_iter().collect();
+        rejections.sort_by_key(|rejection| rejection.line);
+
+        let mut sink = RejectionSink::new(RejectionFormat::Csv, writer);
+        for rejection in rejections {
+            if let EngineError::Rejected(err) = &rejection.error {
+                sink.record(&Rejection::new(rejection.line, &rejection.record, err))?;
+            }
+        }
+        sink.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Server;
+    use crate::processor::TxProcessorImpl;
+    use crate::shard::ShardedEngine;
+    use crate::state::AppState;
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::Shutdown;
+    use std::net::SocketAddr;
+    use std::net::TcpListener;
+    use std::net::TcpStream;
+    use std::thread;
+
+    fn start_server() -> SocketAddr {
+        let engine = ShardedEngine::start(AppState::new(), 2, TxProcessorImpl::new);
+        let server = Server::new(engine.handle());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+        addr
+    }
+
+    fn exchange(addr: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn concurrent_streams_update_shared_accounts() {
+        let addr = start_server();
+        let streams: Vec<_> = (1..=4_u32)
+            .map(|client| {
+                thread::spawn(move || {
+                    let mut csv = String::from("type, client, tx, amount\n");
+                    for i in 0..50 {
+                        let tx = client * 1000 + i;
+                        csv.push_str(&format!("deposit, {}, {}, 2.0\n", client, tx));
+                        csv.push_str(&format!("withdrawal, {}, {}, 1.5\n", client, tx + 500));
+                    }
+                    exchange(addr, &csv)
+                })
+            })
+            .collect();
+        for stream in streams {
+            assert_eq!(stream.join().unwrap(), "");
+        }
+
+        let accounts = exchange(addr, "accounts 3\n");
+        assert_eq!(
+            accounts,
+            "client,available,held,total,locked\n3,25.0000,0.0000,25.0000,false\n"
+        );
+    }
+
+    #[test]
+    fn stream_is_answered_with_its_rejections() {
+        let addr = start_server();
+        let response = exchange(
+            addr,
+            "type,client,tx,amount\ndeposit,1,1,1.0\nwithdrawal,1,2,5.0\n",
+        );
+        assert_eq!(
+            response,
+            "line,type,client,tx,cause,timestamp\n3,withdrawal,1,2,InsufficientFunds,\n"
+        );
+    }
+
+    #[test]
+    fn malformed_row_ends_the_stream() {
+        let addr = start_server();
+        let response = exchange(
+            addr,
+            "type,client,tx,amount\ndeposit,1,1,\ndeposit,1,2,1.0\n",
+        );
+        assert!(response.starts_with("error: line 2: "));
+        assert_eq!(exchange(addr, "accounts\n"), "");
+    }
+}
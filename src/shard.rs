@@ -0,0 +1,561 @@
+use crate::account::ClientAccount;
+use crate::engine::Engine;
+use crate::errors::EngineError;
+use crate::errors::ErrCause;
+use crate::errors::TxProcessingError;
+use crate::processor::TxProcessorImpl;
+use crate::record::Record;
+use crate::state::AppState;
+use std::collections::HashMap;
+use std::error::Error;
+use std::mem;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::thread;
+use std::thread::JoinHandle;
+
+/// Number of records sent to a shard at once.
+pub const BATCH_SIZE: usize = 512;
+
+/// Batches in flight per shard before the reader blocks.
+const QUEUE_DEPTH: usize = 64;
+
+#[derive(Debug)]
+pub struct ShardRejection {
+    pub line: u64,
+    pub record: Record,
+    pub error: EngineError,
+}
+
+/// Shard each transaction id was last sent to.
+type TxOwners = HashMap<u32, usize>;
+
+enum Command {
+    /// Applies the records in order, reporting rejections to `rejections`.
+    Apply {
+        records: Vec<(u64, Record)>,
+        rejections: Sender<ShardRejection>,
+    },
+    /// Replies whether the shard has accepted a transaction with this id.
+    KnowsTx { tx: u32, reply: Sender<bool> },
+    /// Replies with the accounts of the shard, or of one client only.
+    Accounts {
+        client: Option<u16>,
+        reply: Sender<Vec<ClientAccount>>,
+    },
+}
+
+/// Applies transactions on one worker thread per shard, partitioned by client.
+/// A dispute of another shard's transaction is rejected as `ClientTxNotFound`
+/// instead of `ClientDidNotMatch`; transfers need a single shard.
+pub struct ShardedEngine {
+    handle: ShardHandle,
+    pending: Vec<Vec<(u64, Record)>>,
+    rejections: (Sender<ShardRejection>, Receiver<ShardRejection>),
+    workers: Vec<JoinHandle<AppState>>,
+}
+
+impl ShardedEngine {
+    /// Splits `state` into `shards` parts and starts a worker for each, using
+    /// a processor built by `processor`.
+    pub fn start<F>(state: AppState, shards: usize, processor: F) -> Self
+    where
+        F: Fn() -> TxProcessorImpl,
+    {
+        let shards = shards.max(1);
+        let mut senders = Vec::with_capacity(shards);
+        let mut workers = Vec::with_capacity(shards);
+        let mut tx_owners = HashMap::new();
+
+        for (shard, state) in state
+            .split(shards, |client| shard_of(client, shards))
+            .into_iter()
+            .enumerate()
+        {
+            tx_owners.extend(state.tx_ids.iter().map(|tx| (*tx, shard)));
+            let (sender, receiver) = mpsc::sync_channel::<Command>(QUEUE_DEPTH);
+            let engine = Engine::with_state(state).with_processor(processor());
+            workers.push(thread::spawn(move || run_shard(engine, receiver)));
+            senders.push(sender);
+        }
+
+        Self {
+            handle: ShardHandle {
+                senders,
+                tx_owners: Arc::new(Mutex::new(tx_owners)),
+            },
+            pending: (0..shards)
+                .map(|_| Vec::with_capacity(BATCH_SIZE))
+                .collect(),
+            rejections: mpsc::channel(),
+            workers,
+        }
+    }
+
+    pub fn shards(&self) -> usize {
+        self.handle.shards()
+    }
+
+    /// A handle for submitting records and reading accounts from other
+    /// threads. [`ShardedEngine::finish`] waits until every handle is dropped.
+    pub fn handle(&self) -> ShardHandle {
+        self.handle.clone()
+    }
+
+    /// Queues `record` on the shard of its client.
+    pub fn submit_record_at(&mut self, line: u64, record: Record) -> Result<(), Box<dyn Error>> {
+        let shards = self.shards();
+        let shard = shard_of(record.client, shards);
+        let (pending, rejections) = (&mut self.pending, &self.rejections.0);
+        let (rejected, _tx_owners) = match sharded_transfer(&record, shards) {
+            Some(error) => (Some(error), None),
+            None => {
+                let (rejected, tx_owners) = self.handle.take_tx_id(&record, shard, |owner| {
+                    send_pending(&self.handle, pending, rejections, owner)
+                })?;
+                (rejected, Some(tx_owners))
+            }
+        };
+        if let Some(error) = rejected {
+            let _result = rejections.send(ShardRejection {
+                line,
+                record,
+                error,
+            });
+            return Ok(());
+        }
+        pending[shard].push((line, record));
+        if pending[shard].len() >= BATCH_SIZE {
+            send_pending(&self.handle, pending, rejections, shard)?;
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, shard: usize) -> Result<(), Box<dyn Error>> {
+        send_pending(&self.handle, &mut self.pending, &self.rejections.0, shard)
+    }
+
+    /// Waits for every shard to apply its records and merges the shards back
+    /// into one state. Rejections are returned in input order.
+    pub fn finish(mut self) -> Result<(AppState, Vec<ShardRejection>), Box<dyn Error>> {
+        for shard in 0..self.shards() {
+            if !self.pending[shard].is_empty() {
+                self.send(shard)?;
+            }
+        }
+        drop(self.handle);
+        let (sender, receiver) = self.rejections;
+        drop(sender);
+
+        let mut state = AppState::new();
+        for worker in self.workers {
+            let shard_state = worker
+                .join()
+                .map_err(|_| Box::<dyn Error>::from("Shard worker panicked"))?;
+            state.merge(shard_state);
+        }
+        let mut rejections: Vec<ShardRejection> = receiver.into_iter().collect();
+        rejections.sort_by_key(|rejection| rejection.line);
+        Ok((state, rejections))
+    }
+}
+
+/// Cloneable access to the shards of a [`ShardedEngine`].
+#[derive(Clone)]
+pub struct ShardHandle {
+    senders: Vec<SyncSender<Command>>,
+    tx_owners: Arc<Mutex<TxOwners>>,
+}
+
+impl ShardHandle {
+    pub fn shards(&self) -> usize {
+        self.senders.len()
+    }
+
+    /// Queues `record` on the shard of its client, sending any rejection to
+    /// `rejections`.
+    pub fn submit_record_at(
+        &self,
+        line: u64,
+        record: Record,
+        rejections: &Sender<ShardRejection>,
+    ) -> Result<(), Box<dyn Error>> {
+        let shard = shard_of(record.client, self.shards());
+        let (rejected, _tx_owners) = match sharded_transfer(&record, self.shards()) {
+            Some(error) => (Some(error), None),
+            None => {
+                let (rejected, tx_owners) = self.take_tx_id(&record, shard, |_| Ok(()))?;
+                (rejected, Some(tx_owners))
+            }
+        };
+        if let Some(error) = rejected {
+            let _result = rejections.send(ShardRejection {
+                line,
+                record,
+                error,
+            });
+            return Ok(());
+        }
+        self.send(
+            shard,
+            Command::Apply {
+                records: vec![(line, record)],
+                rejections: rejections.clone(),
+            },
+        )
+    }
+
+    /// Applies `record` and waits for the outcome.
+    pub fn apply_record(&self, record: Record) -> Result<Result<(), EngineError>, Box<dyn Error>> {
+        let (rejected, rejections) = mpsc::channel();
+        self.submit_record_at(0, record, &rejected)?;
+        drop(rejected);
+        Ok(match rejections.recv() {
+            Ok(rejection) => Err(rejection.error),
+            Err(_) => Ok(()),
+        })
+    }
+
+    /// Current accounts of every client, after all records queued so far.
+    pub fn accounts(&self) -> Result<Vec<ClientAccount>, Box<dyn Error>> {
+        let (reply, replies) = mpsc::channel();
+        for shard in 0..self.shards() {
+            self.send(
+                shard,
+                Command::Accounts {
+                    client: None,
+                    reply: reply.clone(),
+                },
+            )?;
+        }
+        drop(reply);
+        Ok(replies.into_iter().flatten().collect())
+    }
+
+    /// Current accounts of `client`, one per currency.
+    pub fn client_accounts(&self, client: u16) -> Result<Vec<ClientAccount>, Box<dyn Error>> {
+        let (reply, replies) = mpsc::channel();
+        self.send(
+            shard_of(client, self.shards()),
+            Command::Accounts {
+                client: Some(client),
+                reply,
+            },
+        )?;
+        replies
+            .recv()
+            .map_err(|_| Box::<dyn Error>::from("Shard worker stopped"))
+    }
+
+    /// Records that the transaction of `record` is sent to `shard`, or returns
+    /// its rejection if another shard already accepted the same id. `flush`
+    /// sends the records still queued for a shard. Keep the returned guard
+    /// until the record is queued.
+    fn take_tx_id<F>(
+        &self,
+        record: &Record,
+        shard: usize,
+        mut flush: F,
+    ) -> Result<(Option<EngineError>, MutexGuard<'_, TxOwners>), Box<dyn Error>>
+    where
+        F: FnMut(usize) -> Result<(), Box<dyn Error>>,
+    {
+        let mut owners = self
+            .tx_owners
+            .lock()
+            .map_err(|_| Box::<dyn Error>::from("Transaction id registry poisoned"))?;
+        let error = match record.record_type.as_str() {
+            "deposit" => TxProcessingError::Deposit,
+            "withdrawal" => TxProcessingError::Withdrawal,
+            "transfer" => TxProcessingError::Transfer,
+            _ => return Ok((None, owners)),
+        };
+        let rejected = match owners.insert(record.tx, shard) {
+            Some(owner) if owner != shard => {
+                flush(owner)?;
+                let (reply, replies) = mpsc::channel();
+                self.send(
+                    owner,
+                    Command::KnowsTx {
+                        tx: record.tx,
+                        reply,
+                    },
+                )?;
+                let known = replies
+                    .recv()
+                    .map_err(|_| Box::<dyn Error>::from("Shard worker stopped"))?;
+                if known {
+                    owners.insert(record.tx, owner);
+                    Some(EngineError::Rejected(error(
+                        ErrCause::DuplicateTransactionId,
+                    )))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        };
+        Ok((rejected, owners))
+    }
+
+    fn send(&self, shard: usize, command: Command) -> Result<(), Box<dyn Error>> {
+        self.senders[shard]
+            .send(command)
+            .map_err(|_| Box::<dyn Error>::from(format!("Shard {} stopped", shard)))
+    }
+}
+
+fn run_shard(mut engine: Engine, commands: Receiver<Command>) -> AppState {
+    for command in commands {
+        match command {
+            Command::Apply {
+                records,
+                rejections,
+            } => {
+                for (line, record) in records {
+                    if let Err(error) = engine.submit_record_at(line, &record) {
+                        // Nobody waiting for rejections is not an error.
+                        let _result = rejections.send(ShardRejection {
+                            line,
+                            record,
+                            error,
+                        });
+                    }
+                }
+            }
+            Command::KnowsTx { tx, reply } => {
+                let _result = reply.send(engine.state().tx_ids.contains(&tx));
+            }
+            Command::Accounts { client, reply } => {
+                let accounts = engine
+                    .accounts()
+                    .filter(|account| client.is_none_or(|client| account.client == client))
+                    .cloned()
+                    .collect();
+                let _result = reply.send(accounts);
+            }
+        }
+    }
+    engine.into_state()
+}
+
+/// Sends the records queued for `shard`, if any.
+fn send_pending(
+    handle: &ShardHandle,
+    pending: &mut [Vec<(u64, Record)>],
+    rejections: &Sender<ShardRejection>,
+    shard: usize,
+) -> Result<(), Box<dyn Error>> {
+    if pending[shard].is_empty() {
+        return Ok(());
+    }
+    let records = mem::replace(&mut pending[shard], Vec::with_capacity(BATCH_SIZE));
+    handle.send(
+        shard,
+        Command::Apply {
+            records,
+            rejections: rejections.clone(),
+        },
+    )
+}
+
+pub fn shard_of(client: u16, shards: usize) -> usize {
+    client as usize % shards
+}
+
+/// The rejection of a transfer applied by more than one shard.
+fn sharded_transfer(record: &Record, shards: usize) -> Option<EngineError> {
+    (record.record_type == "transfer" && shards > 1).then_some(EngineError::Rejected(
+        TxProcessingError::Transfer(ErrCause::TransferNeedsOneShard),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedEngine;
+    use crate::amount::Amount;
+    use crate::engine::Engine;
+    use crate::errors::EngineError;
+    use crate::errors::ErrCause;
+    use crate::errors::TxProcessingError;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::record::Record;
+    use crate::state::AppState;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::sync::Barrier;
+    use std::thread;
+
+    fn records() -> Vec<(u64, Record)> {
+        let mut records = Vec::new();
+        let mut line = 1;
+        for client in 0..20_u16 {
+            for tx in 0..30_u32 {
+                let tx_id = client as u32 * 1000 + tx;
+                let (record_type, amount, tx_ref) = match tx % 5 {
+                    0 | 1 => ("deposit", Some("3.5"), tx_id),
+                    2 => ("withdrawal", Some("2.25"), tx_id),
+                    3 => ("dispute", None, tx_id - 3),
+                    _ => ("resolve", None, tx_id - 4),
+                };
+                line += 1;
+                records.push((line, record(record_type, client, tx_ref, amount)));
+            }
+        }
+        // Ids taken by clients of other shards: 1000 was accepted for client
+        // 1, and 99 is only accepted the second time as client 3 cannot pay.
+        for (record_type, client, tx, amount) in [
+            ("deposit", 2, 1000, Some("1.0")),
+            ("withdrawal", 3, 99, Some("1000.0")),
+            ("deposit", 2, 99, Some("1.0")),
+            ("deposit", 3, 99, Some("1.0")),
+        ] {
+            line += 1;
+            records.push((line, record(record_type, client, tx, amount)));
+        }
+        records
+    }
+
+    #[test]
+    fn sharded_run_matches_sequential_run() {
+        let mut engine = Engine::new();
+        let mut expected = Vec::new();
+        for (line, record) in records() {
+            if let Err(error) = engine.submit_record_at(line, &record) {
+                expected.push((line, error));
+            }
+        }
+
+        let mut sharded = ShardedEngine::start(AppState::new(), 4, TxProcessorImpl::new);
+        for (line, record) in records() {
+            sharded.submit_record_at(line, record).unwrap();
+        }
+        let (state, rejections) = sharded.finish().unwrap();
+
+        let rejections: Vec<_> = rejections
+            .into_iter()
+            .map(|rejection| (rejection.line, rejection.error))
+            .collect();
+        assert_eq!(rejections, expected);
+        assert_eq!(state.accounts, engine.state().accounts);
+        assert_eq!(state.deposits, engine.state().deposits);
+        assert_eq!(state.withdrawals, engine.state().withdrawals);
+    }
+
+    #[test]
+    fn handles_submitting_at_once_accept_each_id_once() {
+        let sharded = ShardedEngine::start(AppState::new(), 2, TxProcessorImpl::new);
+        let (rejected, rejections) = mpsc::channel();
+        let start = Arc::new(Barrier::new(2));
+        let submitters: Vec<_> = [0, 1]
+            .into_iter()
+            .map(|client| {
+                let handle = sharded.handle();
+                let rejected = rejected.clone();
+                let start = Arc::clone(&start);
+                thread::spawn(move || {
+                    start.wait();
+                    for tx in 0..10_000 {
+                        let record = record("deposit", client, tx, Some("1.0"));
+                        handle.submit_record_at(0, record, &rejected).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(rejected);
+        for submitter in submitters {
+            submitter.join().unwrap();
+        }
+        let (state, _) = sharded.finish().unwrap();
+
+        assert_eq!(state.deposits.len(), 10_000);
+        assert_eq!(rejections.into_iter().count(), 10_000);
+    }
+
+    #[test]
+    fn rejections_are_returned_in_input_order() {
+        let mut sharded = ShardedEngine::start(AppState::new(), 3, TxProcessorImpl::new);
+        for (line, client) in [(2, 1), (3, 2), (4, 3), (5, 4)] {
+            sharded
+                .submit_record_at(
+                    line,
+                    Record {
+                        record_type: "withdrawal".to_string(),
+                        client,
+                        tx: line as u32,
+                        amount: Some("1.0".parse().unwrap()),
+                        currency: None,
+                        destination: None,
+                        timestamp: None,
+                    },
+                )
+                .unwrap();
+        }
+        let (_state, rejections) = sharded.finish().unwrap();
+
+        assert_eq!(
+            rejections
+                .iter()
+                .map(|rejection| rejection.line)
+                .collect::<Vec<_>>(),
+            vec![2, 3, 4, 5]
+        );
+        assert_eq!(
+            rejections[0].error,
+            EngineError::Rejected(TxProcessingError::Withdrawal(
+                ErrCause::ClientAccountNotFound
+            ))
+        );
+    }
+
+    #[test]
+    fn transfers_are_refused_with_more_than_one_shard() {
+        let mut sharded = ShardedEngine::start(AppState::new(), 2, TxProcessorImpl::new);
+        sharded
+            .submit_record_at(1, record("deposit", 1, 1, Some("4.0")))
+            .unwrap();
+        sharded
+            .submit_record_at(2, record("transfer", 1, 2, Some("4.0")).with_destination(3))
+            .unwrap();
+        sharded
+            .submit_record_at(3, record("transfer", 1, 3, Some("4.0")).with_destination(2))
+            .unwrap();
+        let (state, rejections) = sharded.finish().unwrap();
+
+        assert_eq!(state.accounts[&(1, None)].available, "4.0".parse().unwrap());
+        assert!(!state.accounts.contains_key(&(2, None)));
+        assert!(!state.accounts.contains_key(&(3, None)));
+        assert_eq!(
+            rejections
+                .iter()
+                .map(|rejection| rejection.line)
+                .collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        for rejection in rejections {
+            assert_eq!(
+                rejection.error,
+                EngineError::Rejected(TxProcessingError::Transfer(ErrCause::TransferNeedsOneShard))
+            );
+        }
+
+        let mut single = ShardedEngine::start(AppState::new(), 1, TxProcessorImpl::new);
+        single
+            .submit_record_at(1, record("deposit", 1, 1, Some("4.0")))
+            .unwrap();
+        single
+            .submit_record_at(2, record("transfer", 1, 2, Some("4.0")).with_destination(3))
+            .unwrap();
+        let (state, rejections) = single.finish().unwrap();
+
+        assert!(rejections.is_empty());
+        assert_eq!(state.accounts[&(1, None)].available, Amount::ZERO);
+        assert_eq!(state.accounts[&(3, None)].available, "4.0".parse().unwrap());
+    }
+}
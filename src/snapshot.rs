@@ -0,0 +1,258 @@
+use crate::account::ClientAccount;
+use crate::state::AppState;
+use crate::state::FlaggedDeposit;
+use crate::state::FlaggedWithdrawal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::error::Error;
+use std::fs;
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Bumped whenever the snapshot layout changes. Snapshots of other versions
+/// are refused rather than loaded with guessed defaults.
+///
+/// Version 2 added the checkpoint position, currencies, the sequence numbers
+/// of the dispute window and the latest timestamp of each client.
+pub const SNAPSHOT_VERSION: u32 = 2;
+
+/// Serializable copy of the full `AppState`, so a later run can carry on
+/// from where this one stopped instead of re-feeding all history.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Input line the snapshot was taken at, when taken as a checkpoint in
+    /// the middle of a run.
+    pub position: u64,
+    pub accounts: Vec<ClientAccount>,
+    pub deposits: Vec<FlaggedDeposit>,
+    pub withdrawals: Vec<FlaggedWithdrawal>,
+    pub tx_ids: Vec<u32>,
+    /// Ids in `tx_ids` that are transfers.
+    #[serde(default)]
+    pub transfers: Vec<u32>,
+    /// Latest timestamp accepted per client, as `(client, timestamp)`.
+    pub timestamps: Vec<(u16, u64)>,
+}
+
+impl Snapshot {
+    pub fn from_state(state: &AppState) -> Self {
+        Self::at_position(state, 0)
+    }
+
+    pub fn at_position(state: &AppState, position: u64) -> Self {
+        let mut accounts: Vec<ClientAccount> = state.accounts.values().cloned().collect();
+        accounts.sort_by_key(|account| account.key());
+        let mut deposits: Vec<FlaggedDeposit> = state.deposits.values().cloned().collect();
+        deposits.sort_by_key(|flagged| flagged.deposit.tx);
+        let mut withdrawals: Vec<FlaggedWithdrawal> = state.withdrawals.values().cloned().collect();
+        withdrawals.sort_by_key(|flagged| flagged.withdrawal.tx);
+        let mut tx_ids: Vec<u32> = state.tx_ids.iter().copied().collect();
+        tx_ids.sort_unstable();
+        let mut transfers: Vec<u32> = state.transfers.iter().copied().collect();
+        transfers.sort_unstable();
+        let mut timestamps: Vec<(u16, u64)> = state
+            .timestamps
+            .iter()
+            .map(|(client, timestamp)| (*client, *timestamp))
+            .collect();
+        timestamps.sort_unstable();
+
+        Self {
+            version: SNAPSHOT_VERSION,
+            position,
+            accounts,
+            deposits,
+            withdrawals,
+            tx_ids,
+            transfers,
+            timestamps,
+        }
+    }
+
+    pub fn into_state(self) -> Result<AppState, Box<dyn Error>> {
+        check_version(self.version)?;
+
+        let mut state = AppState::new();
+        for account in self.accounts {
+            state.accounts.insert(account.key(), account);
+        }
+        for flagged in self.deposits {
+            state.deposits.insert(flagged.deposit.tx, flagged);
+        }
+        for flagged in self.withdrawals {
+            state.withdrawals.insert(flagged.withdrawal.tx, flagged);
+        }
+        state.tx_ids.extend(self.tx_ids);
+        state.transfers.extend(self.transfers);
+        state.timestamps.extend(self.timestamps);
+        Ok(state)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    /// Reads a snapshot, checking its version before its layout so that an
+    /// older snapshot is reported as such.
+    pub fn read_from<R: Read>(reader: R) -> Result<Self, Box<dyn Error>> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let version = value
+            .get("version")
+            .and_then(serde_json::Value::as_u64)
+            .ok_or("Snapshot has no version")?;
+        check_version(u32::try_from(version)?)?;
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Writes the snapshot next to `path` first and renames it into place, so
+    /// an interrupted write never leaves a truncated snapshot behind.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+        drop(writer);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::read_from(BufReader::new(File::open(path)?))
+    }
+}
+
+fn check_version(version: u32) -> Result<(), Box<dyn Error>> {
+    match version {
+        SNAPSHOT_VERSION => Ok(()),
+        _ => Err(Box::<dyn Error>::from(format!(
+            "Unsupported snapshot version {}, expected {}; snapshots of other versions \
+             cannot be loaded, rebuild the state from the transactions instead",
+            version, SNAPSHOT_VERSION
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use super::SNAPSHOT_VERSION;
+    use crate::deposit::Deposit;
+    use crate::dispute::Dispute;
+    use crate::processor::TxProcessorImpl;
+    use crate::state::AppState;
+    use crate::state::Flag;
+    use crate::tx::Tx;
+    use crate::withdrawal::Withdrawal;
+    use std::env;
+    use std::fs;
+
+    fn state() -> AppState {
+        let mut state = AppState::new();
+        let processor = TxProcessorImpl::new();
+        let txs: Vec<Box<dyn Tx>> = vec![
+            Box::new(Deposit {
+                client: 1,
+                tx: 1,
+                amount: "10.0".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            }),
+            Box::new(Deposit {
+                client: 2,
+                tx: 2,
+                amount: "5.5".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            }),
+            Box::new(Withdrawal {
+                client: 1,
+                tx: 3,
+                amount: "2.25".parse().unwrap(),
+                currency: None,
+                timestamp: None,
+            }),
+            Box::new(Dispute {
+                client: 2,
+                tx: 2,
+                timestamp: None,
+            }),
+        ];
+        for tx in txs {
+            tx.process(&mut state, &processor).unwrap();
+        }
+        state
+    }
+
+    #[test]
+    fn snapshot_round_trips_the_state() {
+        let state = state();
+        let mut bytes = Vec::new();
+        Snapshot::from_state(&state).write_to(&mut bytes).unwrap();
+
+        let restored = Snapshot::read_from(bytes.as_slice())
+            .unwrap()
+            .into_state()
+            .unwrap();
+
+        assert_eq!(restored.accounts, state.accounts);
+        assert_eq!(restored.deposits, state.deposits);
+        assert_eq!(restored.withdrawals, state.withdrawals);
+        assert_eq!(restored.tx_ids, state.tx_ids);
+        assert_eq!(restored.deposits.get(&2).unwrap().flag, Flag::Disputed);
+    }
+
+    #[test]
+    fn snapshot_is_deterministic() {
+        let first = serde_json::to_string(&Snapshot::from_state(&state())).unwrap();
+        let second = serde_json::to_string(&Snapshot::from_state(&state())).unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn snapshots_are_saved_through_a_temporary_file_of_their_own() {
+        let dir = env::temp_dir().join(format!("tx_engine_snapshot_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let other = dir.join("state.tmp");
+        fs::write(&other, "kept").unwrap();
+
+        let path = dir.join("state.json");
+        Snapshot::from_state(&state()).save(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&other).unwrap(), "kept");
+        assert!(!dir.join("state.json.tmp").exists());
+        assert_eq!(
+            Snapshot::load(&path).unwrap(),
+            Snapshot::from_state(&state())
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn snapshot_with_unknown_version_is_refused() {
+        let mut snapshot = Snapshot::from_state(&state());
+        snapshot.version = SNAPSHOT_VERSION + 1;
+
+        assert!(snapshot.into_state().is_err());
+    }
+
+    #[test]
+    fn snapshot_of_an_older_version_is_refused() {
+        let old = r#"{"version":1,"accounts":[],"deposits":[],"withdrawals":[],"tx_ids":[]}"#;
+        let err = Snapshot::read_from(old.as_bytes()).unwrap_err();
+
+        assert!(err
+            .to_string()
+            .starts_with("Unsupported snapshot version 1, expected 2"));
+    }
+}
@@ -0,0 +1,655 @@
+use crate::account::AccountKey;
+use crate::account::ClientAccount;
+use crate::amount::Amount;
+use crate::state::DisputableKind;
+use crate::state::DisputableTx;
+use crate::state::Flag;
+use crate::storage::Storage;
+use crate::storage::StorageError;
+use crate::storage::TxChange;
+use crate::storage::Update;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Size of one spilled transaction: tx, client, kind, flag, amount, the index
+/// of the currency, the sequence number and the timestamp, `u64::MAX` if none,
+/// little endian. A transaction kept by id only has just its tx and kind set.
+const RECORD_SIZE: usize = 36;
+
+/// Kind of a spilled transfer, kept by id only.
+const TRANSFER_KIND: u8 = 2;
+
+/// Kind of a deposit or withdrawal pruned after its dispute window, kept by id
+/// only.
+const PRUNED_KIND: u8 = 3;
+
+/// Runs are merged into one once there are this many.
+const MAX_RUNS: usize = 8;
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> Self {
+        StorageError(err.to_string())
+    }
+}
+
+/// In-memory storage whose deposits, withdrawals and transfers are moved to
+/// disk once `max_in_memory` undisputed ones have piled up, so memory use no
+/// longer grows with the length of the input.
+///
+/// Spilled transactions are written to `dir` as runs: files of fixed-size
+/// records sorted by transaction id, looked up by binary search, newest run
+/// first. A spilled transaction that is disputed again is brought back into
+/// memory; disputed transactions are never spilled. Accounts and the latest
+/// timestamp of each client always stay in memory. The runs are deleted when
+/// the storage is dropped.
+///
+/// Deposits and withdrawals out of their dispute window are pruned when they
+/// are written to a run and when runs are merged: only their id is kept, to
+/// refuse it again.
+#[derive(Debug)]
+pub struct SpillingStorage {
+    accounts: HashMap<AccountKey, ClientAccount>,
+    timestamps: HashMap<u16, u64>,
+    /// Transactions not spilled yet. Transfers are `None`: only their id is
+    /// kept.
+    recent: HashMap<u32, Option<DisputableTx>>,
+    runs: Vec<Run>,
+    dir: PathBuf,
+    max_in_memory: usize,
+    spill_at: usize,
+    accepted: u64,
+    /// Undisputed transactions numbered below this can be pruned.
+    prune_before: u64,
+    next_run: u64,
+    currencies: Vec<Option<String>>,
+    currency_ids: HashMap<Option<String>, u32>,
+}
+
+impl SpillingStorage {
+    /// Spills to `dir`, which is created if needed.
+    pub fn create(dir: &Path, max_in_memory: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            accounts: HashMap::new(),
+            timestamps: HashMap::new(),
+            recent: HashMap::new(),
+            runs: Vec::new(),
+            dir: dir.to_path_buf(),
+            max_in_memory,
+            spill_at: max_in_memory,
+            accepted: 0,
+            prune_before: 0,
+            next_run: 0,
+            currencies: vec![None],
+            currency_ids: HashMap::from([(None, 0)]),
+        })
+    }
+
+    /// Number of runs currently on disk.
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    /// Number of transactions currently held in memory.
+    pub fn in_memory(&self) -> usize {
+        self.recent.len()
+    }
+
+    /// Finds a stored transaction; `Some(None)` is one kept by id only.
+    fn lookup(&self, tx: u32) -> Result<Option<Option<DisputableTx>>, StorageError> {
+        if let Some(recent) = self.recent.get(&tx) {
+            return Ok(Some(recent.clone()));
+        }
+        for run in self.runs.iter().rev() {
+            if let Some(record) = run.find(tx)? {
+                return Ok(Some(self.decode(&record)?));
+            }
+        }
+        Ok(None)
+    }
+
+    fn insert(&mut self, tx: u32, disputable: Option<DisputableTx>) -> Result<(), StorageError> {
+        self.recent.insert(tx, disputable);
+        if self.recent.len() >= self.spill_at {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Writes every undisputed transaction in memory to a new run.
+    fn spill(&mut self) -> Result<(), StorageError> {
+        let mut spilled: Vec<u32> = self
+            .recent
+            .iter()
+            .filter(|(_, disputable)| {
+                disputable
+                    .as_ref()
+                    .is_none_or(|disputable| disputable.flag != Flag::Disputed)
+            })
+            .map(|(tx, _)| *tx)
+            .collect();
+        spilled.sort_unstable();
+
+        if !spilled.is_empty() {
+            let mut records = Vec::with_capacity(spilled.len());
+            for tx in &spilled {
+                if let Some(disputable) = self.recent.remove(tx) {
+                    records.push(self.encode(*tx, &disputable));
+                }
+            }
+            let path = self.next_path();
+            let mut writer = BufWriter::new(File::create(&path)?);
+            for record in &records {
+                writer.write_all(record)?;
+            }
+            writer.flush()?;
+            self.runs.push(Run::open(path)?);
+            if self.runs.len() >= MAX_RUNS {
+                self.merge_runs()?;
+            }
+        }
+        self.spill_at = self.recent.len() + self.max_in_memory;
+        Ok(())
+    }
+
+    /// Merges all runs into one, keeping the newest record of each id and
+    /// pruning the ones out of their dispute window.
+    fn merge_runs(&mut self) -> Result<(), StorageError> {
+        let mut readers = Vec::with_capacity(self.runs.len());
+        let mut heads = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            let mut reader = BufReader::new(File::open(&run.path)?);
+            heads.push(read_record(&mut reader)?);
+            readers.push(reader);
+        }
+
+        let path = self.next_path();
+        let mut writer = BufWriter::new(File::create(&path)?);
+        loop {
+            // Later runs are newer, so on equal ids the last one wins.
+            let mut newest: Option<(usize, u32)> = None;
+            for (i, head) in heads.iter().enumerate() {
+                if let Some(record) = head {
+                    let tx = record_tx(record);
+                    if newest.is_none_or(|(_, min)| tx <= min) {
+                        newest = Some((i, tx));
+                    }
+                }
+            }
+            let (winner, tx) = match newest {
+                None => break,
+                Some(newest) => newest,
+            };
+            if let Some(record) = &heads[winner] {
+                writer.write_all(&self.pruned(*record))?;
+            }
+            for (head, reader) in heads.iter_mut().zip(readers.iter_mut()) {
+                if head.as_ref().map(record_tx) == Some(tx) {
+                    *head = read_record(reader)?;
+                }
+            }
+        }
+        writer.flush()?;
+
+        for run in self.runs.drain(..) {
+            fs::remove_file(&run.path)?;
+        }
+        self.runs.push(Run::open(path)?);
+        Ok(())
+    }
+
+    fn next_path(&mut self) -> PathBuf {
+        self.next_run += 1;
+        self.dir.join(format!("run-{}.idx", self.next_run))
+    }
+
+    fn currency_id(&mut self, currency: &Option<String>) -> u32 {
+        if let Some(id) = self.currency_ids.get(currency) {
+            return *id;
+        }
+        let id = self.currencies.len() as u32;
+        self.currencies.push(currency.clone());
+        self.currency_ids.insert(currency.clone(), id);
+        id
+    }
+
+    /// `record` reduced to its id if it is out of its dispute window and not
+    /// under dispute.
+    fn pruned(&self, record: [u8; RECORD_SIZE]) -> [u8; RECORD_SIZE] {
+        let mut seq = [0; 8];
+        seq.copy_from_slice(&record[20..28]);
+        if record[6] == TRANSFER_KIND
+            || record[6] == PRUNED_KIND
+            || record[7] == 1
+            || u64::from_le_bytes(seq) >= self.prune_before
+        {
+            return record;
+        }
+        let mut id_only = [0; RECORD_SIZE];
+        id_only[0..4].copy_from_slice(&record[0..4]);
+        id_only[6] = PRUNED_KIND;
+        id_only
+    }
+
+    fn encode(&mut self, tx: u32, disputable: &Option<DisputableTx>) -> [u8; RECORD_SIZE] {
+        let mut record = [0; RECORD_SIZE];
+        record[0..4].copy_from_slice(&tx.to_le_bytes());
+        let disputable = match disputable {
+            None => {
+                record[6] = TRANSFER_KIND;
+                return record;
+            }
+            Some(disputable) => disputable,
+        };
+        record[4..6].copy_from_slice(&disputable.client.to_le_bytes());
+        record[6] = match disputable.kind {
+            DisputableKind::Deposit => 0,
+            DisputableKind::Withdrawal => 1,
+        };
+        record[7] = match disputable.flag {
+            Flag::NotDisputed => 0,
+            Flag::Disputed => 1,
+            Flag::Resolved => 2,
+            Flag::Chargebacked => 3,
+        };
+        record[8..16].copy_from_slice(&disputable.amount.raw().to_le_bytes());
+        let currency = self.currency_id(&disputable.currency);
+        record[16..20].copy_from_slice(&currency.to_le_bytes());
+        record[20..28].copy_from_slice(&disputable.seq.to_le_bytes());
+        let timestamp = disputable.timestamp.unwrap_or(u64::MAX);
+        record[28..36].copy_from_slice(&timestamp.to_le_bytes());
+        self.pruned(record)
+    }
+
+    fn decode(&self, record: &[u8; RECORD_SIZE]) -> Result<Option<DisputableTx>, StorageError> {
+        let corrupt = || StorageError("corrupt spilled transaction".to_string());
+        if record[6] == TRANSFER_KIND || record[6] == PRUNED_KIND {
+            return Ok(None);
+        }
+        let mut amount = [0; 8];
+        amount.copy_from_slice(&record[8..16]);
+        let mut seq = [0; 8];
+        seq.copy_from_slice(&record[20..28]);
+        let mut timestamp = [0; 8];
+        timestamp.copy_from_slice(&record[28..36]);
+        let currency = u32::from_le_bytes([record[16], record[17], record[18], record[19]]);
+        Ok(Some(DisputableTx {
+            kind: match record[6] {
+                0 => DisputableKind::Deposit,
+                1 => DisputableKind::Withdrawal,
+                _ => return Err(corrupt()),
+            },
+            client: u16::from_le_bytes([record[4], record[5]]),
+            currency: self
+                .currencies
+                .get(currency as usize)
+                .ok_or_else(corrupt)?
+                .clone(),
+            amount: Amount::from_raw(i64::from_le_bytes(amount)),
+            flag: match record[7] {
+                0 => Flag::NotDisputed,
+                1 => Flag::Disputed,
+                2 => Flag::Resolved,
+                3 => Flag::Chargebacked,
+                _ => return Err(corrupt()),
+            },
+            seq: u64::from_le_bytes(seq),
+            timestamp: Some(u64::from_le_bytes(timestamp)).filter(|&t| t != u64::MAX),
+        }))
+    }
+}
+
+impl Drop for SpillingStorage {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(&run.path);
+        }
+    }
+}
+
+impl Storage for SpillingStorage {
+    fn account(&self, key: &AccountKey) -> Result<Option<ClientAccount>, StorageError> {
+        Ok(self.accounts.get(key).cloned())
+    }
+
+    fn is_known_tx(&self, tx: u32) -> Result<bool, StorageError> {
+        Ok(self.lookup(tx)?.is_some())
+    }
+
+    fn is_transfer(&self, tx: u32) -> Result<bool, StorageError> {
+        if let Some(recent) = self.recent.get(&tx) {
+            return Ok(recent.is_none());
+        }
+        for run in self.runs.iter().rev() {
+            if let Some(record) = run.find(tx)? {
+                return Ok(record[6] == TRANSFER_KIND);
+            }
+        }
+        Ok(false)
+    }
+
+    fn accepted(&self) -> Result<u64, StorageError> {
+        Ok(self.accepted)
+    }
+
+    fn disputable(&self, tx: u32) -> Result<Option<DisputableTx>, StorageError> {
+        Ok(self.lookup(tx)?.flatten())
+    }
+
+    fn last_timestamp(&self, client: u16) -> Result<Option<u64>, StorageError> {
+        Ok(self.timestamps.get(&client).copied())
+    }
+
+    fn apply(&mut self, update: &Update) -> Result<(), StorageError> {
+        self.accounts
+            .insert(update.account.key(), update.account.clone());
+        if let Some(counterpart) = &update.counterpart {
+            self.accounts.insert(counterpart.key(), counterpart.clone());
+        }
+        if let Some(timestamp) = update.timestamp {
+            self.timestamps.insert(update.account.client, timestamp);
+        }
+        if let (Some(counterpart), Some(timestamp)) =
+            (&update.counterpart, update.counterpart_timestamp)
+        {
+            self.timestamps.insert(counterpart.client, timestamp);
+        }
+        if let Some(before) = update.prune_before {
+            self.prune_before = self.prune_before.max(before);
+        }
+        match &update.tx {
+            TxChange::Deposit(flagged) => {
+                let deposit = &flagged.deposit;
+                self.accepted += 1;
+                self.insert(
+                    deposit.tx,
+                    Some(DisputableTx {
+                        kind: DisputableKind::Deposit,
+                        client: deposit.client,
+                        currency: deposit.currency.clone(),
+                        amount: deposit.amount,
+                        flag: flagged.flag.clone(),
+                        seq: flagged.seq,
+                        timestamp: deposit.timestamp,
+                    }),
+                )?
+            }
+            TxChange::Withdrawal(flagged) => {
+                let withdrawal = &flagged.withdrawal;
+                self.accepted += 1;
+                self.insert(
+                    withdrawal.tx,
+                    Some(DisputableTx {
+                        kind: DisputableKind::Withdrawal,
+                        client: withdrawal.client,
+                        currency: withdrawal.currency.clone(),
+                        amount: withdrawal.amount,
+                        flag: flagged.flag.clone(),
+                        seq: flagged.seq,
+                        timestamp: withdrawal.timestamp,
+                    }),
+                )?
+            }
+            TxChange::Flag(tx, flag) => {
+                if let Some(Some(mut disputable)) = self.lookup(*tx)? {
+                    disputable.flag = flag.clone();
+                    self.insert(*tx, Some(disputable))?
+                }
+            }
+            TxChange::Transfer(tx) => {
+                self.accepted += 1;
+                self.insert(*tx, None)?
+            }
+        }
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<ClientAccount>, StorageError> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+}
+
+/// One spilled file, sorted by transaction id.
+#[derive(Debug)]
+struct Run {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    first: u32,
+    last: u32,
+}
+
+impl Run {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let file = File::open(&path)?;
+        let len = file.metadata()?.len() / RECORD_SIZE as u64;
+        let mut run = Self {
+            path,
+            file,
+            len,
+            first: 0,
+            last: 0,
+        };
+        if len > 0 {
+            run.first = record_tx(&run.read(0)?);
+            run.last = record_tx(&run.read(len - 1)?);
+        }
+        Ok(run)
+    }
+
+    fn read(&self, index: u64) -> io::Result<[u8; RECORD_SIZE]> {
+        let mut record = [0; RECORD_SIZE];
+        let mut file = &self.file;
+        file.seek(SeekFrom::Start(index * RECORD_SIZE as u64))?;
+        file.read_exact(&mut record)?;
+        Ok(record)
+    }
+
+    fn find(&self, tx: u32) -> io::Result<Option<[u8; RECORD_SIZE]>> {
+        if self.len == 0 || tx < self.first || tx > self.last {
+            return Ok(None);
+        }
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            let record = self.read(mid)?;
+            match record_tx(&record).cmp(&tx) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Ok(Some(record)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn record_tx(record: &[u8; RECORD_SIZE]) -> u32 {
+    u32::from_le_bytes([record[0], record[1], record[2], record[3]])
+}
+
+fn read_record<R: Read>(reader: &mut R) -> io::Result<Option<[u8; RECORD_SIZE]>> {
+    let mut record = [0; RECORD_SIZE];
+    match reader.read_exact(&mut record) {
+        Ok(()) => Ok(Some(record)),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpillingStorage;
+    use crate::engine::Engine;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::state::DisputePolicy;
+    use crate::storage::Storage;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn spill_dir(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("tx_engine_spill_{}_{}", name, std::process::id()))
+    }
+
+    #[test]
+    fn spilled_transactions_can_still_be_disputed() {
+        let dir = spill_dir("dispute");
+        let mut in_memory = Engine::new();
+        let mut spilling = Engine::with_storage(SpillingStorage::create(&dir, 2).unwrap());
+
+        let mut records = Vec::new();
+        for tx in 1..=40 {
+            let client = (tx % 3) as u16 + 1;
+            records.push(record("deposit", client, tx, Some("10.0")));
+            if tx % 4 == 0 {
+                records.push(record("withdrawal", client, tx + 1000, Some("1.0")));
+            }
+        }
+        for tx in [2001, 2002] {
+            records.push(record("transfer", 1, tx, Some("0.5")).with_destination(2));
+        }
+        for tx in [1, 2, 3, 1004, 1008] {
+            let client = (tx % 3) as u16 + 1;
+            records.push(record("dispute", client, tx, None));
+        }
+        records.push(record("resolve", 2, 1, None));
+        records.push(record("chargeback", 3, 2, None));
+        records.push(record("dispute", 2, 1, None));
+        records.push(record("deposit", 1, 30, Some("1.0")));
+        records.push(record("resolve", 3, 1008, None));
+        records.push(record("dispute", 1, 2001, None));
+        records.push(record("transfer", 1, 2002, Some("0.5")).with_destination(3));
+
+        for record in &records {
+            assert_eq!(
+                spilling.submit_record(record),
+                in_memory.submit_record(record),
+                "{:?}",
+                record
+            );
+        }
+        assert!(spilling.storage().runs() > 0);
+
+        let mut expected: Vec<_> = in_memory.accounts().cloned().collect();
+        let mut accounts = spilling.storage().accounts().unwrap();
+        expected.sort_by_key(|account| account.key());
+        accounts.sort_by_key(|account| account.key());
+        assert_eq!(accounts, expected);
+
+        drop(spilling);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn runs_are_merged_and_memory_stays_bounded() {
+        let dir = spill_dir("merge");
+        let mut engine = Engine::with_storage(SpillingStorage::create(&dir, 5).unwrap());
+        for tx in 1..=500 {
+            engine
+                .submit_record(&record("deposit", 1, tx, Some("1.0")))
+                .unwrap();
+            assert!(engine.storage().in_memory() <= 5);
+        }
+        assert!(engine.storage().runs() < super::MAX_RUNS);
+
+        for tx in 1..=500 {
+            assert!(engine.storage().is_known_tx(tx).unwrap());
+        }
+        assert!(!engine.storage().is_known_tx(501).unwrap());
+        engine
+            .submit_record(&record("dispute", 1, 7, None))
+            .unwrap();
+        let disputed = engine.storage().disputable(7).unwrap().unwrap();
+        assert_eq!(disputed.amount, "1.0".parse().unwrap());
+
+        drop(engine);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ids_out_of_the_dispute_window_are_still_refused() {
+        let dir = spill_dir("window");
+        let policy = DisputePolicy {
+            dispute_window: Some(2),
+            ..DisputePolicy::default()
+        };
+        let mut in_memory = Engine::new().with_processor(TxProcessorImpl::with_policy(policy));
+        let mut spilling = Engine::with_storage(SpillingStorage::create(&dir, 100).unwrap())
+            .with_processor(TxProcessorImpl::with_policy(policy));
+
+        let mut records: Vec<_> = (1..=5)
+            .map(|tx| record("deposit", 1, tx, Some("1.0")))
+            .collect();
+        records.push(record("deposit", 1, 1, Some("1.0")));
+        records.push(record("dispute", 1, 1, None));
+        for record in &records {
+            assert_eq!(
+                spilling.submit_record(record),
+                in_memory.submit_record(record),
+                "{:?}",
+                record
+            );
+        }
+
+        drop(spilling);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn transactions_out_of_the_dispute_window_are_pruned_to_their_id() {
+        let dir = spill_dir("prune");
+        let policy = DisputePolicy {
+            dispute_window: Some(3),
+            ..DisputePolicy::default()
+        };
+        let mut in_memory = Engine::new().with_processor(TxProcessorImpl::with_policy(policy));
+        let mut spilling = Engine::with_storage(SpillingStorage::create(&dir, 2).unwrap())
+            .with_processor(TxProcessorImpl::with_policy(policy));
+
+        let mut records = vec![record("deposit", 1, 1, Some("1.0"))];
+        records.push(record("dispute", 1, 1, None));
+        for tx in 2..=60 {
+            records.push(record("deposit", 1, tx, Some("1.0")));
+            if tx == 10 {
+                records.push(record("transfer", 1, 100, Some("1.0")).with_destination(2));
+            }
+        }
+        records.push(record("resolve", 1, 1, None));
+        records.push(record("dispute", 1, 2, None));
+        records.push(record("deposit", 1, 2, Some("1.0")));
+        records.push(record("dispute", 1, 60, None));
+        records.push(record("dispute", 1, 100, None));
+        for record in &records {
+            assert_eq!(
+                spilling.submit_record(record),
+                in_memory.submit_record(record),
+                "{:?}",
+                record
+            );
+        }
+
+        let storage = spilling.storage();
+        assert!(storage.runs() > 0);
+        assert!(storage.is_known_tx(2).unwrap());
+        assert_eq!(storage.disputable(2).unwrap(), None);
+        assert!(storage.disputable(1).unwrap().is_some());
+        assert!(storage.disputable(60).unwrap().is_some());
+        assert!(storage.is_transfer(100).unwrap());
+        assert!(!storage.is_transfer(2).unwrap());
+
+        drop(spilling);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
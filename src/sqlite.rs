@@ -0,0 +1,475 @@
+use crate::account::AccountKey;
+use crate::account::ClientAccount;
+use crate::amount::Amount;
+use crate::state::DisputableKind;
+use crate::state::DisputableTx;
+use crate::state::Flag;
+use crate::storage::Storage;
+use crate::storage::StorageError;
+use crate::storage::TxChange;
+use crate::storage::Update;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::OptionalExtension;
+use rusqlite::Row;
+use std::path::Path;
+
+/// Stored in `PRAGMA user_version`; databases of another version are refused.
+const SCHEMA_VERSION: u32 = 2;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER NOT NULL,
+        currency TEXT,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        total INTEGER NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS accounts_client ON accounts (client);
+    CREATE TABLE IF NOT EXISTS transactions (
+        tx INTEGER PRIMARY KEY,
+        kind TEXT NOT NULL,
+        client INTEGER NOT NULL,
+        currency TEXT,
+        amount INTEGER NOT NULL,
+        flag TEXT NOT NULL,
+        seq INTEGER NOT NULL,
+        timestamp INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS transactions_seq ON transactions (seq);
+    CREATE TABLE IF NOT EXISTS pruned (
+        tx INTEGER PRIMARY KEY
+    );
+    CREATE TABLE IF NOT EXISTS clients (
+        client INTEGER PRIMARY KEY,
+        timestamp INTEGER NOT NULL
+    );
+";
+
+/// Storage in an embedded SQLite database, for state that does not fit in
+/// memory or has to outlive the process.
+///
+/// Amounts are stored as integer units of 1/10000; the unnamed currency is
+/// stored as NULL, so it never collides with a currency named by the input.
+/// Every [`Update`] is applied in one database
+/// transaction, so both accounts of a transfer are written or neither is.
+/// Transfers are stored without an amount, as they cannot be disputed.
+/// Transactions out of their dispute window and not under dispute are moved
+/// to the `pruned` table, which only keeps their ids to refuse them again.
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Connection,
+    accepted: u64,
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(err: rusqlite::Error) -> Self {
+        StorageError(err.to_string())
+    }
+}
+
+impl SqliteStorage {
+    /// Opens the database at `path`, creating it if needed.
+    pub fn open(path: &Path) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// A database that lives in memory only, mostly for tests.
+    pub fn in_memory() -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        // WAL keeps commits cheap; with NORMAL a power loss can drop the last
+        // commits but never corrupts the database.
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let tables: u32 =
+            conn.query_row("SELECT COUNT(*) FROM sqlite_master", [], |row| row.get(0))?;
+        if version != SCHEMA_VERSION && (version != 0 || tables != 0) {
+            return Err(StorageError(format!(
+                "Unsupported database version {}, expected {}",
+                version, SCHEMA_VERSION
+            )));
+        }
+        conn.execute_batch(SCHEMA)?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        let accepted = conn.query_row(
+            "SELECT (SELECT COUNT(*) FROM transactions) + (SELECT COUNT(*) FROM pruned)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(Self { conn, accepted })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn account(&self, key: &AccountKey) -> Result<Option<ClientAccount>, StorageError> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT client, currency, available, held, total, locked
+             FROM accounts WHERE client = ?1 AND currency IS ?2",
+        )?;
+        Ok(statement
+            .query_row(params![key.0, key.1], account)
+            .optional()?)
+    }
+
+    fn is_known_tx(&self, tx: u32) -> Result<bool, StorageError> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT 1 FROM transactions WHERE tx = ?1 UNION ALL SELECT 1 FROM pruned WHERE tx = ?1",
+        )?;
+        Ok(statement.exists(params![tx])?)
+    }
+
+    fn is_transfer(&self, tx: u32) -> Result<bool, StorageError> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT 1 FROM transactions WHERE tx = ?1 AND kind = 'transfer'")?;
+        Ok(statement.exists(params![tx])?)
+    }
+
+    fn accepted(&self) -> Result<u64, StorageError> {
+        Ok(self.accepted)
+    }
+
+    fn disputable(&self, tx: u32) -> Result<Option<DisputableTx>, StorageError> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT kind, client, currency, amount, flag, seq, timestamp FROM transactions
+             WHERE tx = ?1 AND kind != 'transfer'",
+        )?;
+        let row = statement
+            .query_row(params![tx], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u16>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, u64>(5)?,
+                    row.get::<_, Option<u64>>(6)?,
+                ))
+            })
+            .optional()?;
+        row.map(|(kind, client, currency, amount, flag, seq, timestamp)| {
+            Ok(DisputableTx {
+                kind: match kind.as_str() {
+                    "deposit" => DisputableKind::Deposit,
+                    "withdrawal" => DisputableKind::Withdrawal,
+                    other => return Err(StorageError(format!("unknown kind {}", other))),
+                },
+                client,
+                currency,
+                amount: Amount::from_raw(amount),
+                flag: parse_flag(&flag)?,
+                seq,
+                timestamp,
+            })
+        })
+        .transpose()
+    }
+
+    fn last_timestamp(&self, client: u16) -> Result<Option<u64>, StorageError> {
+        let mut statement = self
+            .conn
+            .prepare_cached("SELECT timestamp FROM clients WHERE client = ?1")?;
+        Ok(statement
+            .query_row(params![client], |row| row.get(0))
+            .optional()?)
+    }
+
+    fn apply(&mut self, update: &Update) -> Result<(), StorageError> {
+        let db_tx = self.conn.transaction()?;
+        {
+            let account = &update.account;
+            // `IS` matches the NULL currency too, which a primary key would not.
+            let mut update_account = db_tx.prepare_cached(
+                "UPDATE accounts SET available = ?3, held = ?4, total = ?5, locked = ?6
+                 WHERE client = ?1 AND currency IS ?2",
+            )?;
+            let mut insert_account = db_tx.prepare_cached(
+                "INSERT INTO accounts (client, currency, available, held, total, locked)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for account in std::iter::once(account).chain(&update.counterpart) {
+                let values = params![
+                    account.client,
+                    account.currency,
+                    account.available.raw(),
+                    account.held.raw(),
+                    account.total.raw(),
+                    account.locked,
+                ];
+                if update_account.execute(values)? == 0 {
+                    insert_account.execute(values)?;
+                }
+            }
+
+            let timestamps = [
+                update
+                    .timestamp
+                    .map(|timestamp| (account.client, timestamp)),
+                update
+                    .counterpart
+                    .as_ref()
+                    .zip(update.counterpart_timestamp)
+                    .map(|(counterpart, timestamp)| (counterpart.client, timestamp)),
+            ];
+            for (client, timestamp) in timestamps.into_iter().flatten() {
+                let mut statement = db_tx.prepare_cached(
+                    "INSERT OR REPLACE INTO clients (client, timestamp) VALUES (?1, ?2)",
+                )?;
+                statement.execute(params![client, timestamp])?;
+            }
+
+            let mut insert = db_tx.prepare_cached(
+                "INSERT INTO transactions (tx, kind, client, currency, amount, flag, seq, timestamp)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            match &update.tx {
+                TxChange::Deposit(flagged) => {
+                    let deposit = &flagged.deposit;
+                    insert.execute(params![
+                        deposit.tx,
+                        "deposit",
+                        deposit.client,
+                        deposit.currency,
+                        deposit.amount.raw(),
+                        flag_name(&flagged.flag),
+                        flagged.seq,
+                        deposit.timestamp,
+                    ])?;
+                }
+                TxChange::Withdrawal(flagged) => {
+                    let withdrawal = &flagged.withdrawal;
+                    insert.execute(params![
+                        withdrawal.tx,
+                        "withdrawal",
+                        withdrawal.client,
+                        withdrawal.currency,
+                        withdrawal.amount.raw(),
+                        flag_name(&flagged.flag),
+                        flagged.seq,
+                        withdrawal.timestamp,
+                    ])?;
+                }
+                TxChange::Flag(tx, flag) => {
+                    let mut statement =
+                        db_tx.prepare_cached("UPDATE transactions SET flag = ?2 WHERE tx = ?1")?;
+                    statement.execute(params![tx, flag_name(flag)])?;
+                }
+                TxChange::Transfer(tx) => {
+                    insert.execute(params![
+                        tx,
+                        "transfer",
+                        account.client,
+                        account.currency,
+                        0,
+                        flag_name(&Flag::NotDisputed),
+                        0,
+                        None::<u64>,
+                    ])?;
+                }
+            }
+
+            if let Some(before) = update.prune_before {
+                // Transfers are numbered 0, so they are moved along.
+                let expired = "FROM transactions WHERE seq < ?1 AND flag != 'disputed'";
+                db_tx
+                    .prepare_cached(&format!("INSERT INTO pruned (tx) SELECT tx {}", expired))?
+                    .execute(params![before])?;
+                db_tx
+                    .prepare_cached(&format!("DELETE {}", expired))?
+                    .execute(params![before])?;
+            }
+        }
+        db_tx.commit()?;
+        if !matches!(update.tx, TxChange::Flag(..)) {
+            self.accepted += 1;
+        }
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<ClientAccount>, StorageError> {
+        let mut statement = self.conn.prepare_cached(
+            "SELECT client, currency, available, held, total, locked FROM accounts",
+        )?;
+        let accounts = statement
+            .query_map([], account)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(accounts)
+    }
+}
+
+fn account(row: &Row) -> rusqlite::Result<ClientAccount> {
+    Ok(ClientAccount {
+        client: row.get(0)?,
+        currency: row.get(1)?,
+        available: Amount::from_raw(row.get(2)?),
+        held: Amount::from_raw(row.get(3)?),
+        total: Amount::from_raw(row.get(4)?),
+        locked: row.get(5)?,
+    })
+}
+
+fn flag_name(flag: &Flag) -> &'static str {
+    match flag {
+        Flag::NotDisputed => "not_disputed",
+        Flag::Disputed => "disputed",
+        Flag::Resolved => "resolved",
+        Flag::Chargebacked => "chargebacked",
+    }
+}
+
+fn parse_flag(name: &str) -> Result<Flag, StorageError> {
+    match name {
+        "not_disputed" => Ok(Flag::NotDisputed),
+        "disputed" => Ok(Flag::Disputed),
+        "resolved" => Ok(Flag::Resolved),
+        "chargebacked" => Ok(Flag::Chargebacked),
+        other => Err(StorageError(format!("unknown flag {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SqliteStorage;
+    use crate::engine::Engine;
+    use crate::errors::EngineError;
+    use crate::errors::ErrCause;
+    use crate::errors::TxProcessingError;
+    use crate::fixtures::record;
+    use crate::processor::TxProcessorImpl;
+    use crate::record::Record;
+    use crate::state::DisputePolicy;
+    use crate::storage::Storage;
+    use std::env;
+    use std::fs;
+
+    fn records() -> Vec<Record> {
+        vec![
+            record("deposit", 1, 1, Some("10.0")),
+            record("deposit", 2, 2, Some("5.0")),
+            record("withdrawal", 1, 3, Some("2.5")),
+            record("withdrawal", 2, 4, Some("6.0")),
+            record("dispute", 1, 1, None),
+            record("deposit", 1, 3, Some("1.0")),
+            record("dispute", 2, 2, None),
+            record("resolve", 2, 2, None),
+            record("chargeback", 1, 1, None),
+            record("transfer", 2, 5, Some("1.0")).with_destination(1),
+            record("transfer", 2, 6, Some("1.0")).with_destination(3),
+            record("dispute", 2, 6, None),
+            record("deposit", 2, 6, Some("1.0")),
+        ]
+    }
+
+    #[test]
+    fn sqlite_storage_matches_in_memory_state() {
+        let mut in_memory = Engine::new();
+        let mut stored = Engine::with_storage(SqliteStorage::in_memory().unwrap());
+        for record in records() {
+            assert_eq!(
+                stored.submit_record(&record),
+                in_memory.submit_record(&record)
+            );
+        }
+
+        let mut expected: Vec<_> = in_memory.accounts().cloned().collect();
+        let mut accounts = stored.storage().accounts().unwrap();
+        expected.sort_by_key(|account| account.key());
+        accounts.sort_by_key(|account| account.key());
+        assert_eq!(accounts, expected);
+    }
+
+    #[test]
+    fn an_empty_currency_is_not_the_unnamed_one() {
+        let mut stored = Engine::with_storage(SqliteStorage::in_memory().unwrap());
+        stored
+            .submit_record(&record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        stored
+            .submit_record(&record("deposit", 1, 2, Some("2.0")).with_currency(""))
+            .unwrap();
+        stored
+            .submit_record(&record("deposit", 1, 3, Some("1.0")))
+            .unwrap();
+
+        let storage = stored.storage();
+        let unnamed = storage.account(&(1, None)).unwrap().unwrap();
+        let empty = storage.account(&(1, Some(String::new()))).unwrap().unwrap();
+        assert_eq!(unnamed.total, "11.0".parse().unwrap());
+        assert_eq!(empty.total, "2.0".parse().unwrap());
+        assert_eq!(storage.accounts().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn transactions_out_of_the_dispute_window_are_pruned_to_their_id() {
+        let policy = DisputePolicy {
+            dispute_window: Some(3),
+            ..DisputePolicy::default()
+        };
+        let mut in_memory = Engine::new().with_processor(TxProcessorImpl::with_policy(policy));
+        let mut stored = Engine::with_storage(SqliteStorage::in_memory().unwrap())
+            .with_processor(TxProcessorImpl::with_policy(policy));
+
+        let mut records = vec![record("deposit", 1, 1, Some("1.0"))];
+        records.push(record("dispute", 1, 1, None));
+        for tx in 2..=10 {
+            records.push(record("deposit", 1, tx, Some("1.0")));
+        }
+        records.push(record("transfer", 1, 11, Some("1.0")).with_destination(2));
+        records.push(record("resolve", 1, 1, None));
+        records.push(record("dispute", 1, 2, None));
+        records.push(record("deposit", 1, 2, Some("1.0")));
+        records.push(record("dispute", 1, 10, None));
+        records.push(record("dispute", 1, 11, None));
+        for record in &records {
+            assert_eq!(
+                stored.submit_record(record),
+                in_memory.submit_record(record),
+                "{:?}",
+                record
+            );
+        }
+
+        let storage = stored.storage();
+        assert!(storage.is_known_tx(2).unwrap());
+        assert_eq!(storage.disputable(2).unwrap(), None);
+        assert!(storage.disputable(1).unwrap().is_some());
+        assert!(storage.is_transfer(11).unwrap());
+        assert!(!storage.is_transfer(2).unwrap());
+        assert_eq!(storage.accepted().unwrap(), 11);
+    }
+
+    #[test]
+    fn state_is_kept_across_opens() {
+        let path = env::temp_dir().join(format!("tx_engine_sqlite_{}.db", std::process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut engine = Engine::with_storage(SqliteStorage::open(&path).unwrap());
+        engine
+            .submit_record(&record("deposit", 1, 1, Some("10.0")))
+            .unwrap();
+        drop(engine);
+
+        let mut engine = Engine::with_storage(SqliteStorage::open(&path).unwrap());
+        assert_eq!(
+            engine.submit_record(&record("deposit", 1, 1, Some("10.0"))),
+            Err(EngineError::Rejected(TxProcessingError::Deposit(
+                ErrCause::DuplicateTransactionId
+            )))
+        );
+        engine
+            .submit_record(&record("dispute", 1, 1, None))
+            .unwrap();
+        let account = engine.storage().account(&(1, None)).unwrap().unwrap();
+        assert_eq!(account.held, "10.0".parse().unwrap());
+
+        drop(engine);
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+}
@@ -1,8 +1,15 @@
+use crate::account::AccountKey;
 use crate::account::ClientAccount;
+use crate::amount::Amount;
 use crate::deposit::Deposit;
+use crate::errors::ErrCause;
+use crate::withdrawal::Withdrawal;
+use serde::Deserialize;
+use serde::Serialize;
 use std::collections::HashMap;
+use std::collections::HashSet;
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum Flag {
     NotDisputed,
     Disputed,
@@ -10,34 +17,174 @@ pub enum Flag {
     Chargebacked,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FlagEvent {
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+/// Configurable parts of the dispute lifecycle. A chargeback is always final.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct DisputePolicy {
+    pub allow_redispute_after_resolve: bool,
+    /// A deposit or withdrawal can only be disputed until this many further
+    /// deposits, withdrawals and transfers have been accepted. Unlimited if
+    /// `None`.
+    pub dispute_window: Option<u64>,
+    /// A deposit or withdrawal can only be disputed until this many seconds
+    /// after its own timestamp, by the timestamp of the dispute. Transactions
+    /// or disputes without a timestamp are only held to `dispute_window`;
+    /// others have to be within both windows.
+    pub dispute_window_secs: Option<u64>,
+}
+
+impl DisputePolicy {
+    /// Whether the window has closed on the transaction accepted as number
+    /// `seq`, now that `accepted` transactions have been accepted in total.
+    pub fn is_expired(&self, seq: u64, accepted: u64) -> bool {
+        match self.dispute_window {
+            None => false,
+            Some(window) => accepted.saturating_sub(seq + 1) >= window,
+        }
+    }
+
+    /// Whether the time window has closed on a transaction stamped `stamped`
+    /// for a dispute stamped `at`. Never if there is no time window or either
+    /// timestamp is missing.
+    pub fn is_expired_at(&self, stamped: Option<u64>, at: Option<u64>) -> bool {
+        match (self.dispute_window_secs, stamped, at) {
+            (Some(window), Some(stamped), Some(at)) => at.saturating_sub(stamped) > window,
+            _ => false,
+        }
+    }
+
+    /// Transactions accepted before the returned number can no longer be
+    /// disputed once the transaction numbered `seq` has been accepted.
+    /// Only returned every `window` transactions, to prune in batches.
+    pub fn prune_before(&self, seq: u64) -> Option<u64> {
+        match self.dispute_window {
+            Some(window) if (seq + 1).is_multiple_of(window) && seq + 1 > window => {
+                Some(seq + 1 - window)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What to do with a transaction whose timestamp is earlier than the latest
+/// one accepted for its client. Transactions without a timestamp are never
+/// out of order.
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub enum OutOfOrderPolicy {
+    /// Refuse the transaction.
+    #[default]
+    Reject,
+    /// Apply the transaction; the client's latest timestamp stays as it is.
+    Accept,
+}
+
+impl OutOfOrderPolicy {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "reject" => Ok(OutOfOrderPolicy::Reject),
+            "accept" => Ok(OutOfOrderPolicy::Accept),
+            other => Err(format!(
+                "Unknown out-of-order policy: {} (expected reject or accept)",
+                other
+            )),
+        }
+    }
+}
+
+impl Flag {
+    /// The dispute lifecycle:
+    ///
+    /// ```text
+    /// NotDisputed --dispute--> Disputed --resolve----> Resolved
+    ///                                   --chargeback-> Chargebacked
+    /// Resolved --dispute--> Disputed   (only if the policy allows it)
+    /// ```
+    pub fn transition(&self, event: FlagEvent, policy: &DisputePolicy) -> Result<Flag, ErrCause> {
+        match (self, event) {
+            (Flag::NotDisputed, FlagEvent::Dispute) => Ok(Flag::Disputed),
+            (Flag::Resolved, FlagEvent::Dispute) if policy.allow_redispute_after_resolve => {
+                Ok(Flag::Disputed)
+            }
+            (Flag::Disputed, FlagEvent::Resolve) => Ok(Flag::Resolved),
+            (Flag::Disputed, FlagEvent::Chargeback) => Ok(Flag::Chargebacked),
+            (Flag::Disputed, FlagEvent::Dispute) => Err(ErrCause::ClientTxAlreadyInDispute),
+            (Flag::NotDisputed | Flag::Resolved, FlagEvent::Resolve | FlagEvent::Chargeback) => {
+                Err(ErrCause::ClientTxIsNotUnderDispute)
+            }
+            (Flag::Resolved, FlagEvent::Dispute) | (Flag::Chargebacked, _) => {
+                Err(ErrCause::IllegalTxStateTransition)
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct FlaggedDeposit {
     pub deposit: Deposit,
     pub flag: Flag,
+    /// Number of deposits, withdrawals and transfers accepted before this one.
+    pub seq: u64,
 }
 
 impl FlaggedDeposit {
     pub fn is_disputed(&self) -> bool {
         self.flag == Flag::Disputed
     }
+}
 
-    pub fn mark_disputed(&mut self) {
-        self.flag = Flag::Disputed
-    }
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct FlaggedWithdrawal {
+    pub withdrawal: Withdrawal,
+    pub flag: Flag,
+    /// Number of deposits, withdrawals and transfers accepted before this one.
+    pub seq: u64,
+}
 
-    pub fn mark_resolved(&mut self) {
-        self.flag = Flag::Resolved
+impl FlaggedWithdrawal {
+    pub fn is_disputed(&self) -> bool {
+        self.flag == Flag::Disputed
     }
+}
 
-    pub fn mark_chargedback(&mut self) {
-        self.flag = Flag::Chargebacked
-    }
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DisputableKind {
+    Deposit,
+    Withdrawal,
+}
+
+/// What dispute, resolve and chargeback need to know about the transaction
+/// they refer to, regardless of whether it credited or debited the account.
+#[derive(Debug, PartialEq, Clone)]
+pub struct DisputableTx {
+    pub kind: DisputableKind,
+    pub client: u16,
+    pub currency: Option<String>,
+    pub amount: Amount,
+    pub flag: Flag,
+    pub seq: u64,
+    pub timestamp: Option<u64>,
 }
 
 #[derive(Debug)]
 pub struct AppState {
-    pub accounts: HashMap<u16, ClientAccount>,
+    pub accounts: HashMap<AccountKey, ClientAccount>,
     pub deposits: HashMap<u32, FlaggedDeposit>,
+    pub withdrawals: HashMap<u32, FlaggedWithdrawal>,
+    /// Ids of every accepted deposit, withdrawal and transfer, so that a
+    /// replayed or duplicated id is refused instead of overwriting the
+    /// original. Ids are kept when their transaction is pruned after its
+    /// dispute window.
+    pub tx_ids: HashSet<u32>,
+    /// Ids of accepted transfers, which can never be disputed.
+    pub transfers: HashSet<u32>,
+    /// Latest timestamp accepted for each client, to keep them in order.
+    pub timestamps: HashMap<u16, u64>,
 }
 
 impl Default for AppState {
@@ -51,18 +198,165 @@ impl AppState {
         Self {
             accounts: HashMap::new(),
             deposits: HashMap::new(),
+            withdrawals: HashMap::new(),
+            tx_ids: HashSet::new(),
+            transfers: HashSet::new(),
+            timestamps: HashMap::new(),
         }
     }
 
-    pub fn get_account_as_mut(&mut self, client: u16) -> Option<&mut ClientAccount> {
-        self.accounts.get_mut(&client)
+    pub fn get_account_as_mut(&mut self, key: &AccountKey) -> Option<&mut ClientAccount> {
+        self.accounts.get_mut(key)
+    }
+
+    /// Partitions the state into `parts` states by client. Ids in `tx_ids`
+    /// that belong to no stored transaction, and transfer ids, are kept in
+    /// every part.
+    pub fn split(self, parts: usize, part_of: impl Fn(u16) -> usize) -> Vec<AppState> {
+        let mut states: Vec<AppState> = (0..parts).map(|_| AppState::new()).collect();
+        let mut owners = HashMap::new();
+        for (key, account) in self.accounts {
+            states[part_of(key.0)].accounts.insert(key, account);
+        }
+        for (tx, flagged) in self.deposits {
+            owners.insert(tx, part_of(flagged.deposit.client));
+            states[part_of(flagged.deposit.client)]
+                .deposits
+                .insert(tx, flagged);
+        }
+        for (tx, flagged) in self.withdrawals {
+            owners.insert(tx, part_of(flagged.withdrawal.client));
+            states[part_of(flagged.withdrawal.client)]
+                .withdrawals
+                .insert(tx, flagged);
+        }
+        for (client, timestamp) in self.timestamps {
+            states[part_of(client)].timestamps.insert(client, timestamp);
+        }
+        for tx in self.tx_ids {
+            match owners.get(&tx) {
+                Some(part) => {
+                    states[*part].tx_ids.insert(tx);
+                }
+                None => {
+                    for state in states.iter_mut() {
+                        state.tx_ids.insert(tx);
+                    }
+                }
+            }
+        }
+        for tx in self.transfers {
+            for state in states.iter_mut() {
+                state.transfers.insert(tx);
+            }
+        }
+        states
     }
 
-    pub fn get_tx_as_mut(&mut self, tx: u32) -> Option<&mut FlaggedDeposit> {
-        self.deposits.get_mut(&tx)
+    /// Moves everything held by `other` into this state.
+    pub fn merge(&mut self, other: AppState) {
+        self.accounts.extend(other.accounts);
+        self.deposits.extend(other.deposits);
+        self.withdrawals.extend(other.withdrawals);
+        self.tx_ids.extend(other.tx_ids);
+        self.transfers.extend(other.transfers);
+        self.timestamps.extend(other.timestamps);
     }
+}
 
-    pub fn get_tx(&self, tx: u32) -> Option<&FlaggedDeposit> {
-        self.deposits.get(&tx)
+#[cfg(test)]
+mod tests {
+    use super::DisputePolicy;
+    use super::Flag;
+    use super::FlagEvent;
+    use crate::errors::ErrCause;
+
+    const EVENTS: [FlagEvent; 3] = [
+        FlagEvent::Dispute,
+        FlagEvent::Resolve,
+        FlagEvent::Chargeback,
+    ];
+
+    #[test]
+    fn dispute_can_be_resolved_or_charged_back() {
+        let policy = DisputePolicy::default();
+        let disputed = Flag::NotDisputed
+            .transition(FlagEvent::Dispute, &policy)
+            .unwrap();
+
+        assert_eq!(disputed, Flag::Disputed);
+        assert_eq!(
+            disputed.transition(FlagEvent::Resolve, &policy),
+            Ok(Flag::Resolved)
+        );
+        assert_eq!(
+            disputed.transition(FlagEvent::Chargeback, &policy),
+            Ok(Flag::Chargebacked)
+        );
+    }
+
+    #[test]
+    fn resolved_tx_cannot_be_disputed_again_by_default() {
+        assert_eq!(
+            Flag::Resolved.transition(FlagEvent::Dispute, &DisputePolicy::default()),
+            Err(ErrCause::IllegalTxStateTransition)
+        );
+    }
+
+    #[test]
+    fn resolved_tx_can_be_disputed_again_if_policy_allows() {
+        let policy = DisputePolicy {
+            allow_redispute_after_resolve: true,
+            ..DisputePolicy::default()
+        };
+        assert_eq!(
+            Flag::Resolved.transition(FlagEvent::Dispute, &policy),
+            Ok(Flag::Disputed)
+        );
+    }
+
+    #[test]
+    fn dispute_window_counts_later_transactions() {
+        let policy = DisputePolicy {
+            dispute_window: Some(3),
+            ..DisputePolicy::default()
+        };
+        assert!(!policy.is_expired(0, 3));
+        assert!(policy.is_expired(0, 4));
+        assert!(!DisputePolicy::default().is_expired(0, 1_000));
+
+        let pruned: Vec<_> = (0..9).filter_map(|seq| policy.prune_before(seq)).collect();
+        assert_eq!(pruned, vec![3, 6]);
+    }
+
+    #[test]
+    fn time_window_compares_the_two_timestamps() {
+        let policy = DisputePolicy {
+            dispute_window: Some(3),
+            dispute_window_secs: Some(60),
+            ..DisputePolicy::default()
+        };
+        assert!(!policy.is_expired_at(Some(1_000), Some(1_060)));
+        assert!(policy.is_expired_at(Some(1_000), Some(1_061)));
+        assert!(!policy.is_expired_at(Some(1_000), None));
+        assert!(!policy.is_expired_at(None, Some(1_000)));
+        assert!(!DisputePolicy::default().is_expired_at(Some(0), Some(1)));
+        // The count window still prunes: a transaction out of it is expired
+        // whatever its timestamp.
+        assert_eq!(policy.prune_before(5), Some(3));
+    }
+
+    #[test]
+    fn chargeback_is_terminal() {
+        let policy = DisputePolicy {
+            allow_redispute_after_resolve: true,
+            ..DisputePolicy::default()
+        };
+        for event in EVENTS {
+            assert_eq!(
+                Flag::Chargebacked.transition(event, &policy),
+                Err(ErrCause::IllegalTxStateTransition)
+            );
+        }
     }
 }
@@ -0,0 +1,191 @@
+use crate::account::AccountKey;
+use crate::account::ClientAccount;
+use crate::errors::ErrCause;
+use crate::state::AppState;
+use crate::state::DisputableKind;
+use crate::state::DisputableTx;
+use crate::state::Flag;
+use crate::state::FlaggedDeposit;
+use crate::state::FlaggedWithdrawal;
+use std::error::Error;
+use std::fmt::Display;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StorageError(pub String);
+
+impl Error for StorageError {}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "storage: {}", self.0)
+    }
+}
+
+impl From<StorageError> for ErrCause {
+    fn from(_: StorageError) -> Self {
+        ErrCause::StorageFailed
+    }
+}
+
+/// What an accepted transaction changes about the transaction it refers to.
+#[derive(Debug, PartialEq, Clone)]
+pub enum TxChange {
+    Deposit(FlaggedDeposit),
+    Withdrawal(FlaggedWithdrawal),
+    /// New flag of a stored deposit or withdrawal.
+    Flag(u32, Flag),
+    /// Id of a transfer, kept only so that it is refused again.
+    Transfer(u32),
+}
+
+/// Everything an accepted transaction writes, applied as a whole.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Update {
+    pub account: ClientAccount,
+    /// The other account of a transfer.
+    pub counterpart: Option<ClientAccount>,
+    pub tx: TxChange,
+    /// Deposits and withdrawals accepted before this number are out of their
+    /// dispute window: unless under dispute they can be forgotten, keeping
+    /// only their ids.
+    pub prune_before: Option<u64>,
+    /// New latest timestamp of the client, if the transaction moved it.
+    pub timestamp: Option<u64>,
+    /// New latest timestamp of the counterpart's client, if the transfer
+    /// moved it.
+    pub counterpart_timestamp: Option<u64>,
+}
+
+impl Update {
+    /// Id of the transaction written, or of the one whose flag changes.
+    pub fn tx_id(&self) -> u32 {
+        match &self.tx {
+            TxChange::Deposit(flagged) => flagged.deposit.tx,
+            TxChange::Withdrawal(flagged) => flagged.withdrawal.tx,
+            TxChange::Flag(tx, _) | TxChange::Transfer(tx) => *tx,
+        }
+    }
+}
+
+/// Where accounts and disputable transactions are kept. Processors read
+/// through it and write every accepted transaction as one [`Update`], so a
+/// backend can apply it atomically.
+pub trait Storage {
+    fn account(&self, key: &AccountKey) -> Result<Option<ClientAccount>, StorageError>;
+
+    /// Whether `tx` is the id of an accepted deposit, withdrawal or transfer.
+    fn is_known_tx(&self, tx: u32) -> Result<bool, StorageError>;
+
+    /// Whether `tx` is the id of an accepted transfer.
+    fn is_transfer(&self, tx: u32) -> Result<bool, StorageError>;
+
+    /// Number of deposits, withdrawals and transfers accepted so far, which
+    /// numbers the next one.
+    fn accepted(&self) -> Result<u64, StorageError>;
+
+    /// Looks up a deposit or withdrawal that can be disputed, unless it was
+    /// pruned.
+    fn disputable(&self, tx: u32) -> Result<Option<DisputableTx>, StorageError>;
+
+    /// Latest timestamp accepted for `client`, if any.
+    fn last_timestamp(&self, client: u16) -> Result<Option<u64>, StorageError>;
+
+    fn apply(&mut self, update: &Update) -> Result<(), StorageError>;
+
+    /// Every account, in no particular order.
+    fn accounts(&self) -> Result<Vec<ClientAccount>, StorageError>;
+}
+
+impl Storage for AppState {
+    fn account(&self, key: &AccountKey) -> Result<Option<ClientAccount>, StorageError> {
+        Ok(self.accounts.get(key).cloned())
+    }
+
+    fn is_known_tx(&self, tx: u32) -> Result<bool, StorageError> {
+        Ok(self.tx_ids.contains(&tx))
+    }
+
+    fn is_transfer(&self, tx: u32) -> Result<bool, StorageError> {
+        Ok(self.transfers.contains(&tx))
+    }
+
+    fn accepted(&self) -> Result<u64, StorageError> {
+        Ok(self.tx_ids.len() as u64)
+    }
+
+    fn disputable(&self, tx: u32) -> Result<Option<DisputableTx>, StorageError> {
+        if let Some(flagged) = self.deposits.get(&tx) {
+            return Ok(Some(DisputableTx {
+                kind: DisputableKind::Deposit,
+                client: flagged.deposit.client,
+                currency: flagged.deposit.currency.clone(),
+                amount: flagged.deposit.amount,
+                flag: flagged.flag.clone(),
+                seq: flagged.seq,
+                timestamp: flagged.deposit.timestamp,
+            }));
+        }
+        Ok(self.withdrawals.get(&tx).map(|flagged| DisputableTx {
+            kind: DisputableKind::Withdrawal,
+            client: flagged.withdrawal.client,
+            currency: flagged.withdrawal.currency.clone(),
+            amount: flagged.withdrawal.amount,
+            flag: flagged.flag.clone(),
+            seq: flagged.seq,
+            timestamp: flagged.withdrawal.timestamp,
+        }))
+    }
+
+    fn last_timestamp(&self, client: u16) -> Result<Option<u64>, StorageError> {
+        Ok(self.timestamps.get(&client).copied())
+    }
+
+    fn apply(&mut self, update: &Update) -> Result<(), StorageError> {
+        self.accounts
+            .insert(update.account.key(), update.account.clone());
+        if let Some(counterpart) = &update.counterpart {
+            self.accounts.insert(counterpart.key(), counterpart.clone());
+        }
+        if let Some(timestamp) = update.timestamp {
+            self.timestamps.insert(update.account.client, timestamp);
+        }
+        if let (Some(counterpart), Some(timestamp)) =
+            (&update.counterpart, update.counterpart_timestamp)
+        {
+            self.timestamps.insert(counterpart.client, timestamp);
+        }
+        match &update.tx {
+            TxChange::Deposit(flagged) => {
+                self.tx_ids.insert(flagged.deposit.tx);
+                self.deposits.insert(flagged.deposit.tx, flagged.clone());
+            }
+            TxChange::Withdrawal(flagged) => {
+                self.tx_ids.insert(flagged.withdrawal.tx);
+                self.withdrawals
+                    .insert(flagged.withdrawal.tx, flagged.clone());
+            }
+            TxChange::Flag(tx, flag) => {
+                if let Some(flagged) = self.deposits.get_mut(tx) {
+                    flagged.flag = flag.clone();
+                } else if let Some(flagged) = self.withdrawals.get_mut(tx) {
+                    flagged.flag = flag.clone();
+                }
+            }
+            TxChange::Transfer(tx) => {
+                self.tx_ids.insert(*tx);
+                self.transfers.insert(*tx);
+            }
+        }
+        if let Some(before) = update.prune_before {
+            self.deposits
+                .retain(|_, flagged| flagged.is_disputed() || flagged.seq >= before);
+            self.withdrawals
+                .retain(|_, flagged| flagged.is_disputed() || flagged.seq >= before);
+        }
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<ClientAccount>, StorageError> {
+        Ok(self.accounts.values().cloned().collect())
+    }
+}
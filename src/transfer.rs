@@ -0,0 +1,184 @@
+use crate::amount::Amount;
+use crate::errors::TxProcessingError;
+use crate::processor::TxProcessor;
+use crate::storage::Storage;
+use crate::tx::Tx;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Moves funds from the available balance of `client` to the one of
+/// `destination`, in the same currency.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
+pub struct Transfer {
+    pub client: u16,
+    pub destination: u16,
+    pub tx: u32,
+    pub amount: Amount,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+impl Tx for Transfer {
+    fn process(
+        &self,
+        storage: &mut dyn Storage,
+        visitor: &dyn TxProcessor,
+    ) -> Result<(), TxProcessingError> {
+        visitor.process_transfer(storage, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transfer;
+    use crate::errors::ErrCause;
+    use crate::errors::TxProcessingError;
+    use crate::fixtures::account;
+    use crate::fixtures::state;
+    use crate::processor::TxProcessorImpl;
+    use crate::storage::Storage;
+    use crate::tx::Tx;
+
+    fn transfer(client: u16, destination: u16, amount: &str) -> Transfer {
+        Transfer {
+            client,
+            destination,
+            tx: 10,
+            amount: amount.parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn transfer_moves_available_funds() {
+        let mut state = state(vec![account(1, "100.0", false), account(2, "5.0", false)]);
+        transfer(1, 2, "40.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(state.accounts[&(1, None)], account(1, "60.0", false));
+        assert_eq!(state.accounts[&(2, None)], account(2, "45.0", false));
+        assert!(state.is_known_tx(10).unwrap());
+        assert_eq!(state.disputable(10).unwrap(), None);
+    }
+
+    #[test]
+    fn transfer_opens_the_destination_account() {
+        let mut state = state(vec![account(1, "100.0", false)]);
+        transfer(1, 3, "100.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(state.accounts[&(1, None)], account(1, "0.0", false));
+        assert_eq!(state.accounts[&(3, None)], account(3, "100.0", false));
+    }
+
+    #[test]
+    fn transfer_fails_when_balance_is_insufficient() {
+        let mut state = state(vec![account(1, "10.0", false), account(2, "5.0", false)]);
+        let err = transfer(1, 2, "10.5")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::InsufficientFunds)
+        );
+        assert_eq!(state.accounts[&(1, None)], account(1, "10.0", false));
+        assert_eq!(state.accounts[&(2, None)], account(2, "5.0", false));
+        assert!(!state.is_known_tx(10).unwrap());
+    }
+
+    #[test]
+    fn transfer_fails_if_either_account_is_locked() {
+        let mut state = state(vec![account(1, "10.0", true), account(2, "5.0", false)]);
+        let err = transfer(1, 2, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::ClientAccountLocked)
+        );
+
+        let err = transfer(2, 1, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::DestinationAccountLocked)
+        );
+        assert_eq!(state.accounts[&(2, None)], account(2, "5.0", false));
+    }
+
+    #[test]
+    fn transfer_fails_without_a_source_account() {
+        let mut state = state(vec![account(2, "5.0", false)]);
+        let err = transfer(1, 2, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::ClientAccountNotFound)
+        );
+    }
+
+    #[test]
+    fn transfer_to_the_same_client_is_refused() {
+        let mut state = state(vec![account(1, "10.0", false)]);
+        let err = transfer(1, 1, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(err, TxProcessingError::Transfer(ErrCause::TransferToSelf));
+    }
+
+    #[test]
+    fn transfer_fails_if_tx_id_is_taken() {
+        let mut state = state(vec![account(1, "10.0", false), account(2, "5.0", false)]);
+        transfer(1, 2, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+        let err = transfer(1, 2, "1.0")
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::DuplicateTransactionId)
+        );
+        assert_eq!(state.accounts[&(1, None)], account(1, "9.0", false));
+    }
+
+    #[test]
+    fn transfer_checks_and_advances_the_destination_timestamp() {
+        let mut state = state(vec![account(1, "10.0", false), account(2, "5.0", false)]);
+        state.timestamps.insert(1, 100);
+        state.timestamps.insert(2, 300);
+
+        let err = Transfer {
+            timestamp: Some(200),
+            ..transfer(1, 2, "1.0")
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap_err();
+        assert_eq!(
+            err,
+            TxProcessingError::Transfer(ErrCause::TimestampOutOfOrder)
+        );
+        assert_eq!(state.accounts[&(2, None)], account(2, "5.0", false));
+        assert_eq!(state.timestamps[&1], 100);
+
+        Transfer {
+            timestamp: Some(400),
+            ..transfer(1, 2, "1.0")
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+        assert_eq!(state.timestamps[&1], 400);
+        assert_eq!(state.timestamps[&2], 400);
+    }
+}
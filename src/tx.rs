@@ -1,12 +1,12 @@
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use std::fmt::Debug;
 
 pub trait Tx: Debug {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError>;
 }
@@ -1,22 +1,29 @@
+use crate::amount::Amount;
 use crate::errors::TxProcessingError;
 use crate::processor::TxProcessor;
-use crate::state::AppState;
+use crate::storage::Storage;
 use crate::tx::Tx;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct Withdrawal {
     pub client: u16,
     pub tx: u32,
-    pub amount: f64,
+    pub amount: Amount,
+    #[serde(default)]
+    pub currency: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
 }
 
 impl Tx for Withdrawal {
     fn process(
         &self,
-        state: &mut AppState,
+        storage: &mut dyn Storage,
         visitor: &dyn TxProcessor,
     ) -> Result<(), TxProcessingError> {
-        visitor.process_withdrawal(state, self)
+        visitor.process_withdrawal(storage, self)
     }
 }
 
@@ -24,33 +31,39 @@ impl Tx for Withdrawal {
 mod tests {
     use super::Withdrawal;
     use crate::account::ClientAccount;
+    use crate::deposit::Deposit;
     use crate::errors::ErrCause;
     use crate::errors::TxProcessingError;
     use crate::processor::TxProcessorImpl;
     use crate::state::AppState;
+    use crate::state::Flag;
+    use crate::state::FlaggedWithdrawal;
     use crate::tx::Tx;
 
     #[test]
     fn withdraw_fails_if_amount_is_zero() {
         let client = 1;
         let zero_withdrawal = Withdrawal {
-            client: client,
+            client,
             tx: 2,
-            amount: 0.0,
+            amount: "0.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
-                client: client,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
+                client,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: false,
+                currency: None,
             },
         );
         let withdrawal_error = zero_withdrawal
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
 
         assert_eq!(
@@ -63,23 +76,26 @@ mod tests {
     fn withdraw_fails_if_amount_is_negative() {
         let client = 1;
         let negative_withdrawal = Withdrawal {
-            client: client,
+            client,
             tx: 2,
-            amount: -100.0,
+            amount: "-100.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
-                client: client,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
+                client,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: false,
+                currency: None,
             },
         );
         let withdrawal_error = negative_withdrawal
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
 
         assert_eq!(
@@ -92,23 +108,26 @@ mod tests {
     fn withdraw_fails_when_balance_is_insufficient() {
         let client = 1;
         let withdrawal = Withdrawal {
-            client: client,
+            client,
             tx: 2,
-            amount: 1000.0,
+            amount: "1000.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
-                client: client,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
+                client,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: false,
+                currency: None,
             },
         );
         let withdrawal_error = withdrawal
-            .process(&mut state, &TxProcessorImpl)
+            .process(&mut state, &TxProcessorImpl::new())
             .unwrap_err();
 
         assert_eq!(
@@ -122,21 +141,26 @@ mod tests {
         let client = 1;
         let mut state = AppState::new();
         state.accounts.insert(
-            client,
+            (client, None),
             ClientAccount {
-                client: client,
-                available: 100.0,
-                held: 0.0,
-                total: 100.0,
+                client,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: true,
+                currency: None,
             },
         );
         let deposit = Withdrawal {
-            client: client,
+            client,
             tx: 1,
-            amount: 10.0,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
-        let withdrawal_error = deposit.process(&mut state, &TxProcessorImpl).unwrap_err();
+        let withdrawal_error = deposit
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
 
         assert_eq!(
             withdrawal_error,
@@ -149,28 +173,34 @@ mod tests {
         let withdrawal = Withdrawal {
             client: 1,
             tx: 2,
-            amount: 10.0,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 10.0,
-                held: 100.0,
-                total: 10.0,
+                currency: None,
+                available: "10.0".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "10.0".parse().unwrap(),
                 locked: false,
             },
         );
-        withdrawal.process(&mut state, &TxProcessorImpl).unwrap();
+        withdrawal
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
-            state.accounts.get(&withdrawal.client).unwrap(),
+            state.accounts.get(&(withdrawal.client, None)).unwrap(),
             &ClientAccount {
                 client: 1,
-                available: 0.0,
-                held: 100.0,
-                total: 0.0,
+                currency: None,
+                available: "0.0".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "0.0".parse().unwrap(),
                 locked: false,
             }
         )
@@ -181,30 +211,121 @@ mod tests {
         let withdrawal = Withdrawal {
             client: 1,
             tx: 2,
-            amount: 10.0,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
         };
         let mut state = AppState::new();
         state.accounts.insert(
-            1,
+            (1, None),
             ClientAccount {
                 client: 1,
-                available: 100.0,
-                held: 100.0,
-                total: 100.0,
+                currency: None,
+                available: "100.0".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: false,
             },
         );
-        withdrawal.process(&mut state, &TxProcessorImpl).unwrap();
+        withdrawal
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
 
         assert_eq!(
-            state.accounts.get(&withdrawal.client).unwrap(),
+            state.accounts.get(&(withdrawal.client, None)).unwrap(),
             &ClientAccount {
                 client: 1,
-                available: 90.0,
-                held: 100.0,
-                total: 90.0,
+                currency: None,
+                available: "90.0".parse().unwrap(),
+                held: "100.0".parse().unwrap(),
+                total: "90.0".parse().unwrap(),
+                locked: false,
+            }
+        )
+    }
+
+    #[test]
+    fn withdrawal_gets_cached() {
+        let withdrawal = Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        state.accounts.insert(
+            (1, None),
+            ClientAccount {
+                client: 1,
+                currency: None,
+                available: "100.0".parse().unwrap(),
+                held: "0.0".parse().unwrap(),
+                total: "100.0".parse().unwrap(),
                 locked: false,
+            },
+        );
+        withdrawal
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap();
+
+        assert_eq!(
+            state.withdrawals.get(&withdrawal.tx).unwrap(),
+            &FlaggedWithdrawal {
+                withdrawal,
+                flag: Flag::NotDisputed,
+                seq: 0,
             }
         )
     }
+
+    #[test]
+    fn rejected_withdrawal_is_not_cached() {
+        let withdrawal = Withdrawal {
+            client: 1,
+            tx: 2,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        };
+        let mut state = AppState::new();
+        withdrawal
+            .process(&mut state, &TxProcessorImpl::new())
+            .unwrap_err();
+
+        assert_eq!(state.withdrawals.get(&withdrawal.tx), None)
+    }
+
+    #[test]
+    fn withdrawal_reusing_a_deposit_tx_id_is_rejected() {
+        let mut state = AppState::new();
+        Deposit {
+            client: 1,
+            tx: 1,
+            amount: "10.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap();
+
+        let duplicate_error = Withdrawal {
+            client: 1,
+            tx: 1,
+            amount: "5.0".parse().unwrap(),
+            currency: None,
+            timestamp: None,
+        }
+        .process(&mut state, &TxProcessorImpl::new())
+        .unwrap_err();
+
+        assert_eq!(
+            duplicate_error,
+            TxProcessingError::Withdrawal(ErrCause::DuplicateTransactionId)
+        );
+        assert_eq!(
+            state.accounts.get(&(1, None)).unwrap().available,
+            "10.0".parse().unwrap()
+        );
+    }
 }